@@ -0,0 +1,110 @@
+//! Headless mode for scripts and scheduled tasks: `--status` and `--pull` run
+//! without opening a window.
+
+use std::path::PathBuf;
+use rayon::prelude::*;
+
+use crate::repo::{self, Repository};
+use crate::{
+    apply_repo_prefs, cell_text, configure_worker_pool, load_config, log_file, summarize_pull_output,
+    visible_columns, walk_repositories, worker_pool, Preferences, COLUMNS,
+};
+
+/// Runs the command-line mode when `args` asks for it and returns the process
+/// exit code; `None` when the GUI should start instead.
+///
+/// Directories given as arguments are searched for repositories, otherwise the
+/// configured list is used. `--pull` pulls every branch of each repository and
+/// `--status` prints a tab-separated table of the visible columns. The exit code
+/// is 1 when a pull failed or a repository is modified or in error.
+pub fn run(args: &[String]) -> Option<i32> {
+    let status = args.iter().any(|a| a == "--status");
+    let pull = args.iter().any(|a| a == "--pull");
+    if !status && !pull {
+        return None;
+    }
+    attach_console();
+
+    let config = load_config();
+    // Full paths, so the output can be fed back to other tools
+    let prefs = Preferences {
+        show_full_path: true,
+        ..config.prefs.clone()
+    };
+    configure_worker_pool(prefs.max_parallelism);
+    repo::set_log_file(prefs.log_to_file.then(log_file));
+    repo::set_hg_env(config.env_vars.clone(), config.repo_env_vars.clone());
+    repo::set_network_retries(config.network_retries.unwrap_or(repo::DEFAULT_NETWORK_RETRIES));
+
+    let dirs: Vec<PathBuf> = args
+        .iter()
+        .skip(1)
+        .filter(|a| !a.starts_with('-'))
+        .map(PathBuf::from)
+        .collect();
+    let mut paths = Vec::new();
+    if dirs.is_empty() {
+        paths = config.repositories.clone();
+    }
+    for dir in &dirs {
+        walk_repositories(dir, |path| paths.push(path));
+    }
+    let mut repos: Vec<Repository> = paths
+        .into_iter()
+        .map(|path| {
+            let mut r = Repository::new(path);
+            r.alias = config.aliases.get(&r.path).cloned();
+            apply_repo_prefs(&mut r, &prefs);
+            r
+        })
+        .collect();
+    repos.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut code = 0;
+    if pull {
+        let results: Vec<_> = worker_pool().install(|| {
+            repos.par_iter().map(|r| r.pull_all_branches()).collect()
+        });
+        for (r, result) in repos.iter().zip(results) {
+            match result {
+                Ok(out) => println!("{}\t{}", r.path.display(), summarize_pull_output(&out)),
+                Err(e) => {
+                    println!("{}\tError: {}", r.path.display(), e);
+                    code = 1;
+                }
+            }
+        }
+    }
+
+    if status {
+        worker_pool().install(|| repos.par_iter_mut().for_each(|r| r.refresh()));
+        let columns = visible_columns(&prefs);
+        let header: Vec<&str> = columns.iter().map(|&i| COLUMNS[i].name).collect();
+        println!("{}", header.join("\t"));
+        for r in &repos {
+            let cells: Vec<String> = columns.iter().map(|&i| cell_text(COLUMNS[i].id, r, &prefs)).collect();
+            println!("{}", cells.join("\t"));
+            if r.modified || r.missing || r.branch_error.is_some() || r.last_status.starts_with("Error") {
+                code = 1;
+            }
+        }
+    }
+    Some(code)
+}
+
+/// Release builds have no console on Windows; output goes to the console of
+/// the shell that started ManaHg, if any.
+#[cfg(target_os = "windows")]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn attach_console() {}
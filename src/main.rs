@@ -2,7 +2,7 @@
 
 use fltk::{
     app,
-    browser::MultiBrowser,
+    browser::{HoldBrowser, MultiBrowser},
     button::Button,
     dialog,
     enums::{Color, FrameType, Key, Shortcut},
@@ -19,135 +19,1015 @@ use serde::{Deserialize, Serialize};
 #[cfg(target_os = "windows")]
 use std::os::windows::process::CommandExt;
 use std::{
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, OnceLock,
+    },
     thread,
+    time::{Duration, Instant},
 };
 use walkdir::WalkDir;
 
+mod cli;
 mod repo;
-use repo::Repository;
+mod tray;
+use repo::{CommitOptions, Operation, Phase, RepoError, Repository};
 
+/// Name of the configuration file, in the folder given by `config_file`.
 const CONFIG_FILE: &str = "configuration.json";
 
+/// Environment variable with the path of the configuration file to use instead
+/// of the per-user one, for portable installs.
+const CONFIG_ENV_VAR: &str = "MANAHG_CONFIG";
+
+/// Operation log written when `Preferences::log_to_file` is set, next to the
+/// configuration file (see `log_file`).
+const LOG_FILE: &str = "manahg.log";
+
+/// Lines kept in the output log window; the oldest go first.
+const MAX_OUTPUT_LOG_LINES: i32 = 5000;
+
+/// Diffs larger than this are cut off in the diff viewer.
+const MAX_DIFF_BYTES: usize = 500 * 1024;
+
+/// Changesets shown when the revision graph window opens.
+const DEFAULT_GRAPH_LIMIT: usize = 30;
+
+/// Changesets listed in the History window.
+const HISTORY_LIMIT: usize = 50;
+
+/// Outgoing changesets listed per repository in the push confirmation.
+const OUTGOING_LIMIT: usize = 20;
+
+/// Pause after which type-ahead find starts over with the next key.
+const TYPE_AHEAD_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Commit message for the merge made by Sync.
+const SYNC_MERGE_MESSAGE: &str = "Merge heads";
+
+/// Widget ids of the counts at the right of the status bar, kept current by
+/// `update_summary_bar`.
+const SUMMARY_COUNTS_ID: &str = "summary_counts";
+const SUMMARY_ERRORS_ID: &str = "summary_errors";
+
 fn default_show_full_path() -> bool {
     true
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+fn default_visible_columns() -> Vec<bool> {
+    COLUMNS.iter().map(|c| c.visible_by_default).collect()
+}
+
+fn serialize_visible_columns<S: serde::Serializer>(flags: &[bool], serializer: S) -> Result<S::Ok, S::Error> {
+    let names: Vec<&str> = COLUMNS
+        .iter()
+        .enumerate()
+        .filter(|(i, c)| flags.get(*i).copied().unwrap_or(c.visible_by_default))
+        .map(|(_, c)| c.name)
+        .collect();
+    names.serialize(serializer)
+}
+
+/// Accepts column names, and the one-flag-per-column list of older configs.
+fn deserialize_visible_columns<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<bool>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Saved {
+        Names(Vec<String>),
+        Flags(Vec<bool>),
+    }
+    Ok(match Saved::deserialize(deserializer)? {
+        Saved::Names(names) => COLUMNS.iter().map(|c| names.iter().any(|n| n == c.name)).collect(),
+        Saved::Flags(flags) => flags,
+    })
+}
+
+/// Saves an index into one of the theme tables as the entry's name.
+fn serialize_table_name<S: serde::Serializer, T>(table: &[(&str, T)], idx: usize, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(table.get(idx).unwrap_or(&table[0]).0)
+}
+
+/// Accepts an entry name, and the plain index of older configs. Unknown names
+/// and stale indices fall back to the first entry.
+fn deserialize_table_name<'de, D: serde::Deserializer<'de>, T>(table: &[(&str, T)], deserializer: D) -> Result<usize, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Saved {
+        Name(String),
+        Index(usize),
+    }
+    Ok(match Saved::deserialize(deserializer)? {
+        Saved::Name(name) => table.iter().position(|(n, _)| *n == name).unwrap_or(0),
+        Saved::Index(idx) if idx < table.len() => idx,
+        Saved::Index(_) => 0,
+    })
+}
+
+fn serialize_theme<S: serde::Serializer>(idx: &usize, serializer: S) -> Result<S::Ok, S::Error> {
+    serialize_table_name(WIDGET_THEMES, *idx, serializer)
+}
+
+fn deserialize_theme<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    deserialize_table_name(WIDGET_THEMES, deserializer)
+}
+
+fn serialize_scheme<S: serde::Serializer>(idx: &usize, serializer: S) -> Result<S::Ok, S::Error> {
+    serialize_table_name(WIDGET_SCHEMES, *idx, serializer)
+}
+
+fn deserialize_scheme<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    deserialize_table_name(WIDGET_SCHEMES, deserializer)
+}
+
+fn serialize_color_theme<S: serde::Serializer>(idx: &usize, serializer: S) -> Result<S::Ok, S::Error> {
+    serialize_table_name(COLOR_THEMES, *idx, serializer)
+}
+
+fn deserialize_color_theme<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    deserialize_table_name(COLOR_THEMES, deserializer)
+}
+
+/// All cores but one, so the event loop keeps a core during batch operations.
+fn default_max_parallelism() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get().saturating_sub(1))
+        .unwrap_or(1)
+        .max(1)
+}
+
+fn default_profile() -> String {
+    "Default".to_string()
+}
+
+fn default_check_heads() -> bool {
+    true
+}
+
+fn default_show_batch_summary() -> bool {
+    true
+}
+
+fn default_color_rows() -> bool {
+    true
+}
+
+fn default_row_icons() -> bool {
+    true
+}
+
+fn default_auto_theme() -> bool {
+    true
+}
+
+fn default_confirm_remove() -> bool {
+    true
+}
+
+fn default_details_panel_width() -> i32 {
+    300
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 struct AppConfig {
+    /// Repositories of the active profile
     repositories: Vec<PathBuf>,
+    /// Repository lists keyed by profile name, the active one included
     #[serde(default)]
-    theme_idx: usize,
+    profiles: HashMap<String, Vec<PathBuf>>,
+    /// Display names keyed by repository path
     #[serde(default)]
-    scheme_idx: usize,
+    aliases: HashMap<PathBuf, String>,
+    /// Environment variables for every hg command, e.g. `HGPLAIN` or `HGUSER`.
+    /// Only edited in the file.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    env_vars: HashMap<String, String>,
+    /// Overrides of `env_vars` keyed by repository path
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    repo_env_vars: HashMap<PathBuf, HashMap<String, String>>,
+    /// Times a pull, incoming or outgoing failing on a network error (e.g.
+    /// "connection reset") is run again; `None` for `repo::DEFAULT_NETWORK_RETRIES`.
+    /// Only edited in the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    network_retries: Option<usize>,
+    /// Text the Commit dialog starts with, placeholders allowed, e.g.
+    /// "[{branch}] ". Only edited in the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_commit_message: Option<String>,
+    /// Name of the column the list is sorted by; none sorts by path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sort_column: Option<String>,
+    #[serde(default)]
+    sort_order: SortOrder,
+    /// Folder last picked to search for repositories, where the chooser opens next time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_scan_dir: Option<PathBuf>,
+    /// Folder last picked as a clone destination
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_clone_dir: Option<PathBuf>,
+    /// Menu shortcuts changed from their defaults, keyed by `action_name`,
+    /// e.g. "Action/Pull All Branches": "Ctrl+Shift+P"
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    shortcuts: HashMap<String, String>,
+    /// Main window geometry when the application was last closed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    window: Option<WindowGeometry>,
+    #[serde(flatten)]
+    prefs: Preferences,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct WindowGeometry {
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+    /// Position and size are those from before the window was maximized
     #[serde(default)]
+    maximized: bool,
+}
+
+/// Settings edited through the Preferences dialog.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Preferences {
+    /// Profile whose repository list is shown, switched from the toolbar
+    #[serde(default = "default_profile")]
+    active_profile: String,
+    /// Saved by name, so reordering `WIDGET_THEMES` keeps the user's choice
+    #[serde(default, rename = "theme", alias = "theme_idx", serialize_with = "serialize_theme", deserialize_with = "deserialize_theme")]
+    theme_idx: usize,
+    /// Start with the Dark theme when the system is in dark mode. Cleared when
+    /// a theme is picked in Preferences.
+    #[serde(default = "default_auto_theme")]
+    auto_theme: bool,
+    #[serde(default, rename = "scheme", alias = "scheme_idx", serialize_with = "serialize_scheme", deserialize_with = "deserialize_scheme")]
+    scheme_idx: usize,
+    #[serde(default, rename = "color_theme", alias = "color_idx", serialize_with = "serialize_color_theme", deserialize_with = "deserialize_color_theme")]
     color_idx: usize,
     #[serde(default = "default_show_full_path")]
     show_full_path: bool,
+    /// Rev column shows the short changeset hash, the same in every clone,
+    /// instead of the local revision number
+    #[serde(default)]
+    show_hash: bool,
+    /// Color rows by state: errors, operations running, uncommitted changes
+    #[serde(default = "default_color_rows")]
+    color_rows: bool,
+    /// Glyphs before the path for the state and phase, see `row_icons`
+    #[serde(default = "default_row_icons")]
+    row_icons: bool,
+    #[serde(default)]
+    default_scope: Scope,
+    /// Per entry of `COLUMNS`; missing entries use the column's default.
+    /// Saved as the names of the shown columns.
+    #[serde(
+        default = "default_visible_columns",
+        serialize_with = "serialize_visible_columns",
+        deserialize_with = "deserialize_visible_columns"
+    )]
+    visible_columns: Vec<bool>,
+    #[serde(default)]
+    group_by_parent: bool,
+    /// Hide repos without uncommitted changes or an error
+    #[serde(default)]
+    modified_only: bool,
+    #[serde(default = "default_confirm_remove")]
+    confirm_remove: bool,
+    #[serde(default)]
+    show_status_panel: bool,
+    #[serde(default)]
+    show_details_panel: bool,
+    /// Width of the details panel, dragged with the splitter on its left
+    #[serde(default = "default_details_panel_width")]
+    details_panel_width: i32,
+    #[serde(default)]
+    recurse_subrepos: bool,
+    /// Count branch heads on refresh to flag repos needing a merge
+    #[serde(default = "default_check_heads")]
+    check_heads: bool,
+    /// Open the Batch Results window when repositories failed in a batch
+    #[serde(default = "default_show_batch_summary")]
+    show_batch_summary: bool,
+    /// Append every hg command and its outcome to `LOG_FILE`
+    #[serde(default)]
+    log_to_file: bool,
+    /// Worker threads running hg at once in batch operations
+    #[serde(default = "default_max_parallelism")]
+    max_parallelism: usize,
+    /// Widths dragged in the header, by column name
+    #[serde(default)]
+    column_widths: HashMap<String, i32>,
+    /// Closing the main window hides it, leaving the tray icon to bring it
+    /// back. Windows only.
+    #[serde(default)]
+    minimize_to_tray: bool,
+    /// Command for Open in VS Code, e.g. "code-insiders"; `None` runs `code`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    vscode_command: Option<String>,
 }
 
-impl Default for AppConfig {
+impl Default for Preferences {
     fn default() -> Self {
         Self {
-            repositories: Vec::new(),
+            active_profile: default_profile(),
             theme_idx: 0, // Default to Greybird
+            auto_theme: true,
             scheme_idx: 0, // Fluent
             color_idx: 0, // None
             show_full_path: true,
+            show_hash: false,
+            color_rows: true,
+            row_icons: true,
+            default_scope: Scope::Selected,
+            visible_columns: default_visible_columns(),
+            group_by_parent: false,
+            modified_only: false,
+            confirm_remove: true,
+            show_status_panel: false,
+            show_details_panel: false,
+            details_panel_width: default_details_panel_width(),
+            recurse_subrepos: false,
+            check_heads: true,
+            show_batch_summary: true,
+            log_to_file: false,
+            max_parallelism: default_max_parallelism(),
+            column_widths: HashMap::new(),
+            minimize_to_tray: false,
+            vscode_command: None,
         }
     }
 }
 
+/// Pool for the per-repository work of batch operations, sized by
+/// `Preferences::max_parallelism` instead of rayon's one thread per core.
+static WORKER_POOL: Mutex<Option<Arc<rayon::ThreadPool>>> = Mutex::new(None);
+
+/// Replaces the worker pool. Batches already running finish on the old one.
+fn configure_worker_pool(threads: usize) {
+    match rayon::ThreadPoolBuilder::new().num_threads(threads.max(1)).build() {
+        Ok(pool) => *WORKER_POOL.lock().unwrap() = Some(Arc::new(pool)),
+        Err(e) => eprintln!("Failed to create worker pool: {}", e),
+    }
+}
+
+fn worker_pool() -> Arc<rayon::ThreadPool> {
+    let mut pool = WORKER_POOL.lock().unwrap();
+    pool.get_or_insert_with(|| {
+        Arc::new(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(default_max_parallelism())
+                .build()
+                .expect("Failed to create worker pool"),
+        )
+    })
+    .clone()
+}
+
+/// Runs `f` on a new thread with its parallel iterators on the worker pool.
+fn spawn_worker<F>(f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    thread::spawn(move || worker_pool().install(f));
+}
+
+/// Batches that can wait for the batch thread; more are refused, see
+/// `refuse_when_queue_full`.
+const BATCH_QUEUE_CAPACITY: usize = 16;
+
+/// Status of the repositories of a batch refused because the queue is full.
+const QUEUE_FULL_STATUS: &str = "Not run: too many operations waiting";
+
+/// Status of the repositories of a batch waiting for the ones queued before it.
+const WAITING_STATUS: &str = "Waiting...";
+
+type BatchTask = Box<dyn FnOnce() + Send>;
+
+/// Feeds the batch thread, started by the first `queue_batch`.
+static BATCH_QUEUE: OnceLock<mpsc::SyncSender<BatchTask>> = OnceLock::new();
+
+/// Batches queued and not started yet.
+static QUEUED_BATCHES: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs `f` on the batch thread, with its parallel iterators on the worker
+/// pool, once the batches queued before it are done. One batch runs at a time,
+/// so a refresh started during a pull waits for it instead of running hg in
+/// the same repositories at once. Callers check `refuse_when_queue_full` first.
+fn queue_batch<F>(f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    let queue = BATCH_QUEUE.get_or_init(|| {
+        let (tx, rx) = mpsc::sync_channel::<BatchTask>(BATCH_QUEUE_CAPACITY);
+        thread::spawn(move || {
+            for task in rx {
+                QUEUED_BATCHES.fetch_sub(1, Ordering::Relaxed);
+                // A panicking batch must not take the queue down with it
+                let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| worker_pool().install(task)));
+            }
+        });
+        tx
+    });
+    QUEUED_BATCHES.fetch_add(1, Ordering::Relaxed);
+    if queue.send(Box::new(f)).is_err() {
+        eprintln!("Batch thread stopped, operation dropped");
+    }
+}
+
+/// When `BATCH_QUEUE_CAPACITY` batches are already waiting, gives `repos`
+/// `QUEUE_FULL_STATUS` and returns true; the batch must not be queued.
+fn refuse_when_queue_full(repos: &[Repository], sender: app::Sender<Message>) -> bool {
+    if QUEUED_BATCHES.load(Ordering::Relaxed) < BATCH_QUEUE_CAPACITY {
+        return false;
+    }
+    for r in repos {
+        sender.send(Message::SetStatus(r.path.clone(), QUEUE_FULL_STATUS.to_string()));
+    }
+    sender.send(Message::SetGlobalStatus(format!(
+        "{} operations are already waiting, try again once some are done",
+        BATCH_QUEUE_CAPACITY
+    )));
+    true
+}
+
+/// Which repositories a batch operation applies to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+enum Scope {
+    #[default]
+    Selected,
+    /// Every repository currently shown in the list
+    View,
+}
+
 // Config Helper
-fn load_config() -> AppConfig {
-    if let Ok(file) = std::fs::File::open(CONFIG_FILE) {
-        // Try loading as AppConfig first
-        if let Ok(cfg) = serde_json::from_reader(file) {
-            return cfg;
-        }
-        // Fallback: Try loading strictly as Vec<PathBuf> for backward compatibility
-        // Re-open file because reader consumed it?
-        if let Ok(file) = std::fs::File::open(CONFIG_FILE) {
-            if let Ok(paths) = serde_json::from_reader::<_, Vec<PathBuf>>(file) {
-                return AppConfig {
-                    repositories: paths,
-                    theme_idx: 0,
-                    scheme_idx: 0,
-                    color_idx: 0,
-                    show_full_path: true,
-                };
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Path of the configuration file: `MANAHG_CONFIG` when set, otherwise
+/// `CONFIG_FILE` in the per-user configuration folder, e.g.
+/// `%APPDATA%\ManaHg`. Its folder is created on first use, and a
+/// `configuration.json` left by older versions in the working directory or next
+/// to the executable is copied there.
+fn config_file() -> &'static Path {
+    CONFIG_PATH.get_or_init(|| {
+        if let Some(path) = std::env::var_os(CONFIG_ENV_VAR).filter(|p| !p.is_empty()) {
+            let path = PathBuf::from(path);
+            if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty())
+                && let Err(e) = std::fs::create_dir_all(dir)
+            {
+                eprintln!("Failed to create {}: {}", dir.display(), e);
+            }
+            return path;
+        }
+        let Some(dir) = user_config_dir() else {
+            return PathBuf::from(CONFIG_FILE);
+        };
+        let dir = dir.join("ManaHg");
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            eprintln!("Failed to create {}: {}", dir.display(), e);
+            return PathBuf::from(CONFIG_FILE);
+        }
+        let path = dir.join(CONFIG_FILE);
+        if !path.exists() {
+            let exe_dir = std::env::current_exe().ok().and_then(|exe| Some(exe.parent()?.to_path_buf()));
+            let old = [Some(PathBuf::from(CONFIG_FILE)), exe_dir.map(|d| d.join(CONFIG_FILE))]
+                .into_iter()
+                .flatten()
+                .find(|old| old.is_file());
+            if let Some(old) = old
+                && let Err(e) = std::fs::copy(&old, &path)
+            {
+                eprintln!("Failed to copy {} to {}: {}", old.display(), path.display(), e);
             }
         }
+        path
+    })
+}
+
+/// The per-user configuration folder of the platform, `None` when its
+/// environment variable is not set.
+fn user_config_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    if cfg!(target_os = "windows") {
+        var("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library/Application Support"))
+    } else {
+        var("XDG_CONFIG_HOME").or_else(|| var("HOME").map(|home| home.join(".config")))
+    }
+}
+
+/// The configuration file path with `suffix` appended, e.g. ".bak".
+fn config_file_with_suffix(suffix: &str) -> PathBuf {
+    let mut path = config_file().as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Path of `LOG_FILE`, next to the configuration file.
+fn log_file() -> PathBuf {
+    config_file().with_file_name(LOG_FILE)
+}
+
+/// Reads the configuration file, or its ".bak" backup when it cannot be read
+/// as a configuration, e.g. after the disk filled up.
+fn load_config() -> AppConfig {
+    if let Some(cfg) = read_config(config_file()) {
+        return cfg;
+    }
+    let backup = config_file_with_suffix(".bak");
+    if config_file().exists()
+        && let Some(cfg) = read_config(&backup)
+    {
+        eprintln!("{} is damaged, using {}", config_file().display(), backup.display());
+        return cfg;
     }
     AppConfig::default()
 }
 
-fn save_config(
-    repos: &[Repository],
-    theme_idx: usize,
-    scheme_idx: usize,
-    color_idx: usize,
-    show_full_path: bool,
-) {
+fn read_config(path: &Path) -> Option<AppConfig> {
+    let text = std::fs::read_to_string(path).ok()?;
+    // Try loading as AppConfig first
+    if let Ok(cfg) = serde_json::from_str(&text) {
+        return Some(cfg);
+    }
+    // Fallback: Try loading strictly as Vec<PathBuf> for backward compatibility
+    let paths = serde_json::from_str::<Vec<PathBuf>>(&text).ok()?;
+    Some(AppConfig {
+        repositories: paths,
+        ..AppConfig::default()
+    })
+}
+
+fn save_config(repos: &[Repository], prefs: &Preferences) {
     let paths: Vec<PathBuf> = repos.iter().map(|r| r.path.clone()).collect();
+    // Only the active profile is in memory; the others are kept from the file
+    let saved = load_config();
+    let mut profiles = saved.profiles;
+    profiles.insert(prefs.active_profile.clone(), paths.clone());
+    let other_paths: HashSet<&PathBuf> = profiles
+        .iter()
+        .filter(|(name, _)| **name != prefs.active_profile)
+        .flat_map(|(_, list)| list)
+        .collect();
+    // Built from the current lists, so aliases of removed repos are dropped
+    let mut aliases: HashMap<PathBuf, String> = saved
+        .aliases
+        .into_iter()
+        .filter(|(path, _)| other_paths.contains(path))
+        .collect();
+    aliases.extend(repos.iter().filter_map(|r| Some((r.path.clone(), r.alias.clone()?))));
     let cfg = AppConfig {
         repositories: paths,
-        theme_idx,
-        scheme_idx,
-        color_idx,
-        show_full_path,
+        profiles,
+        aliases,
+        env_vars: saved.env_vars,
+        repo_env_vars: saved.repo_env_vars,
+        network_retries: saved.network_retries,
+        default_commit_message: saved.default_commit_message,
+        sort_column: saved.sort_column,
+        sort_order: saved.sort_order,
+        last_scan_dir: saved.last_scan_dir,
+        last_clone_dir: saved.last_clone_dir,
+        shortcuts: saved.shortcuts,
+        window: saved.window,
+        prefs: prefs.clone(),
     };
-    match std::fs::File::create(CONFIG_FILE) {
-        Ok(file) => {
-            if let Err(e) = serde_json::to_writer_pretty(file, &cfg) {
-                eprintln!("Failed to write config: {}", e);
-                dialog::alert(200, 200, &format!("Failed to write config: {}", e));
-            }
-        }
-        Err(e) => {
-            eprintln!("Failed to create config file: {}", e);
-            dialog::alert(200, 200, &format!("Failed to create config file: {}", e));
-        }
+    write_config(&cfg);
+}
+
+/// Saves the primary sort key, leaving the rest of the file as it is.
+fn save_sort(state: &SortState) {
+    let mut cfg = load_config();
+    let primary = state.keys.first().and_then(|&(c, order)| Some((COLUMNS.get(c)?, order)));
+    cfg.sort_column = primary.map(|(col, _)| col.name.to_string());
+    cfg.sort_order = primary.map(|(_, order)| order).unwrap_or_default();
+    write_config(&cfg);
+}
+
+/// Saves the changed shortcuts, leaving the rest of the file as it is.
+fn save_shortcuts(shortcuts: &HashMap<String, String>) {
+    let mut cfg = load_config();
+    cfg.shortcuts = shortcuts.clone();
+    write_config(&cfg);
+}
+
+/// Saves a folder picked in a chooser into the `AppConfig` field given by
+/// `field`, leaving the rest of the file as it is.
+fn save_last_dir(field: fn(&mut AppConfig) -> &mut Option<PathBuf>, dir: &Path) {
+    let mut cfg = load_config();
+    *field(&mut cfg) = Some(dir.to_path_buf());
+    write_config(&cfg);
+}
+
+/// Saves the main window geometry, leaving the rest of the file as it is. While
+/// maximized only the flag changes, so the window is restored to its old size.
+fn save_window_geometry(wind: &Window) {
+    let mut cfg = load_config();
+    let maximized = wind.maximize_active();
+    cfg.window = match cfg.window {
+        Some(old) if maximized => Some(WindowGeometry { maximized, ..old }),
+        _ => Some(WindowGeometry {
+            x: wind.x(),
+            y: wind.y(),
+            w: wind.w(),
+            h: wind.h(),
+            maximized,
+        }),
+    };
+    write_config(&cfg);
+}
+
+/// Moves a saved geometry into the work area of the screen it is on, e.g.
+/// after the monitor it was on was disconnected.
+fn clamp_to_work_area(g: &WindowGeometry) -> (i32, i32, i32, i32) {
+    let screen = app::screen_num(g.x + g.w / 2, g.y + g.h / 2);
+    let (sx, sy, sw, sh) = app::screen_work_area(screen);
+    let (w, h) = (g.w.clamp(200, sw.max(200)), g.h.clamp(150, sh.max(150)));
+    let x = g.x.clamp(sx, (sx + sw - w).max(sx));
+    let y = g.y.clamp(sy, (sy + sh - h).max(sy));
+    (x, y, w, h)
+}
+
+/// Writes `cfg` to a ".tmp" file then renames it over the configuration file,
+/// so the file is never left half written. The previous file, if it could be
+/// read, is kept as the ".bak" backup.
+fn write_config(cfg: &AppConfig) {
+    if let Err(e) = replace_config(cfg) {
+        eprintln!("Failed to write config: {}", e);
+        dialog::alert(200, 200, &format!("Failed to write config: {}", e));
+    }
+}
+
+fn replace_config(cfg: &AppConfig) -> anyhow::Result<()> {
+    let text = serde_json::to_string_pretty(cfg)?;
+    let temp = config_file_with_suffix(".tmp");
+    {
+        let mut file = std::fs::File::create(&temp)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+    }
+    if read_config(config_file()).is_some() {
+        std::fs::copy(config_file(), config_file_with_suffix(".bak"))?;
+    }
+    std::fs::rename(&temp, config_file())?;
+    Ok(())
+}
+
+/// The widget theme in use: the Dark theme while following a system in dark
+/// mode, the saved one otherwise. The saved one is kept for light mode.
+fn effective_theme_idx(prefs: &Preferences) -> usize {
+    match WIDGET_THEMES.iter().position(|(name, _)| *name == "Dark") {
+        Some(idx) if prefs.auto_theme && system_dark_mode() == Some(true) => idx,
+        _ => prefs.theme_idx,
+    }
+}
+
+/// Applies the effective widget theme, then the scheme and color palette.
+fn apply_theme(prefs: &Preferences) {
+    if let Some((_, Some(t))) = WIDGET_THEMES.get(effective_theme_idx(prefs)) {
+        WidgetTheme::new(*t).apply();
+    }
+    if let Some((_, Some(s))) = WIDGET_SCHEMES.get(prefs.scheme_idx) {
+        WidgetScheme::new(*s).apply();
+    }
+    if let Some((_, Some(c))) = COLOR_THEMES.get(prefs.color_idx) {
+        ColorTheme::new(c).apply();
     }
 }
 
 #[derive(Clone)]
 enum Message {
-    ScanComplete(Vec<Repository>),
+    /// A scan found and refreshed a repository
+    RepoDiscovered(Repository),
+    /// A scan finished, with the number of repositories it found
+    ScanComplete(usize),
     PullAll,
     PullCurrent,
+    PullSubrepos,
     UpdateLatest,
+    PullAndUpdate,
+    RetryFailed,
+    Sync,
+    CheckRemotes,
+    Push,
+    ConfirmPush(Vec<OutgoingSummary>),
+    /// Repos to push and how many were unchecked in the confirmation
+    DoPush(Vec<PathBuf>, usize),
+    UpdateLastPublic,
+    OpenForceUpdate,
+    /// Repos to update and the dirty ones with how many files they would lose
+    ForceUpdatePreviewed(Vec<PathBuf>, Vec<(PathBuf, usize)>),
+    OpenUpdateRev,
+    /// Revision to update to, and whether to discard local changes (`--clean`)
+    DoUpdateRev(Vec<PathBuf>, String, bool),
+    DoForceUpdate(Vec<PathBuf>),
     Commit,
+    /// Paths, message, whether to run addremove first, and the commit flags
+    DoCommit(Vec<PathBuf>, String, bool, CommitOptions),
+    OpenRevert,
+    /// Dirty repos to revert and their changed files
+    RevertPreviewed(Vec<(PathBuf, Vec<String>)>),
+    AbortInterrupted,
+    ShowDiff,
+    DiffLoaded(PathBuf, Result<String, String>),
+    ShowGraph,
+    /// Load the revision graph of a repository, with the number of changesets
+    LoadGraph(PathBuf, usize),
+    GraphLoaded(PathBuf, Result<String, String>),
+    ShowHistory,
+    /// rev, branch, author, summary of the latest changesets of a repository
+    HistoryLoaded(PathBuf, Result<Vec<(String, String, String, String)>, String>),
+    ShowConflicts,
+    LoadConflicts(PathBuf),
+    ConflictsLoaded(PathBuf, Result<Vec<String>, String>),
+    /// Mark a file resolved, or with `false` run the merge tool on it
+    ResolveFile(PathBuf, String, bool),
+    OpenEditPaths,
+    OpenEditIgnore,
+    /// Repository and the new contents of its `.hgignore`
+    SaveIgnore(PathBuf, String),
+    /// Repository and the `[paths]` entries to write to its `.hg/hgrc`
+    SavePaths(PathBuf, Vec<(String, String)>),
+    OpenChangePhase,
+    ChangePhase(Vec<PathBuf>, String, Phase, bool),
+    DoRevert(Vec<PathBuf>, bool),
+    UndoLast,
+    OpenPurge,
+    /// Repos with files to purge, with their untracked and ignored file counts
+    PurgePreviewed(Vec<(PathBuf, usize, usize)>),
+    /// Repos to purge and whether ignored files go too (`--all`)
+    DoPurge(Vec<PathBuf>, bool),
+    /// Repos where purge failed because the extension is disabled
+    OfferEnablePurge(Vec<PathBuf>, bool),
     OpenSwitchBranch,
-    SwitchBranch(String),
+    /// Repos to switch and their branches with how many of them have each
+    BranchesAnalyzed(Vec<PathBuf>, Vec<(String, usize)>),
+    SwitchBranch(Vec<PathBuf>, String),
+    OpenNewBranch,
+    /// Branch name and, when set, the message of the initial commit
+    NewBranch(Vec<PathBuf>, String, Option<String>),
     OpenUpdateTag,
-    DoUpdateTag(String),
+    /// Repos to update and their tags with how many of them have each
+    TagsAnalyzed(Vec<PathBuf>, Vec<(String, usize)>),
+    CreateTag,
+    DoUpdateTag(Vec<PathBuf>, String),
     Refresh,
     RefreshAll,
     AddFolder,
+    SwitchProfile(String),
+    NewProfile,
+    OpenClone,
+    /// Source URL and destination directory
+    DoClone(String, PathBuf),
+    CloneFinished(Result<Repository, String>),
+    /// Scan folders dropped onto the list
+    ScanFolders(Vec<PathBuf>),
     RemoveSelected,
+    RemoveMissing,
     OpenPreferences,
-    UpdatePreferences(usize, usize, usize, bool),
+    UpdatePreferences(Preferences),
     SelectAll,
+    /// Select the next repository whose shown name starts with the typed text
+    TypeAhead(String),
+    InvertSelection,
+    SelectNone,
+    SelectModified,
+    OpenSelectMatching,
+    /// Select the repositories whose path or branch matches; pattern, case sensitive
+    SelectMatching(String, bool),
+    /// Select the repositories whose last operation failed
+    SelectErrored,
     Copy,
+    CopyTable,
+    CopyHash,
+    /// Clipboard text of Copy Hash, read from hg off the UI thread
+    HashesLoaded(String),
+    CopyBranch,
+    Rename,
+    Locate,
     OpenTortoiseHg,
+    OpenVsCode,
+    /// Double-click on a row: error details for a failed repository, TortoiseHg otherwise
+    RowActivated,
+    ShowErrorDetails,
+    ViewLog,
+    ShowOutputLog,
+    /// An hg command ran; its description for the output log
+    LogLine(String),
+    /// Set a repository's last status, in order with the pending `RepoUpdated`s
     SetStatus(PathBuf, String),
     SetGlobalStatus(String),
+    /// A `spawn_batch` batch is done
+    BatchComplete(BatchSummary),
+    /// Select these repositories' rows, e.g. the failures of a batch
+    SelectPaths(Vec<PathBuf>),
+    /// Shortcuts changed in Preferences, keyed by `action_name`
+    UpdateShortcuts(HashMap<String, String>),
+    /// The main window was moved or resized and has been still for a moment
+    SaveWindowGeometry,
+    /// Save the window geometry and close every window
+    Quit,
+    /// The main window was closed: hidden to the tray when
+    /// `Preferences::minimize_to_tray` is set, otherwise quits
+    CloseWindow,
+    /// The tray icon was clicked, to hide the main window or bring it back
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    ToggleWindow,
+    /// Repositories finished and repositories added by running batches. The
+    /// progress bar shows the sums until every batch is done.
+    OperationProgress(usize, usize),
+    /// Stop the running batches
+    CancelBatches,
+    /// A worker is done with a repository; applied with the other pending
+    /// writes on the next `FlushRepoWrites`
     RepoUpdated(Repository),
-    Sort(usize), // Column Index
+    /// Apply the pending `RepoUpdated` and `SetStatus` writes and redraw once
+    FlushRepoWrites,
+    /// A header was clicked; `true` when Shift was held, to add a secondary key
+    Sort(usize, bool), // Column Index
+    /// A header column was dragged to a new width; `true` once the drag ends
+    ColumnResized(usize, i32, bool), // Visible column position, width
+    /// The window was resized, so the last column takes the new space
+    LayoutColumns,
+    ToggleLegend,
+    SelectionChanged,
+    ToggleStatusPanel,
+    ToggleGroupByParent,
+    ToggleModifiedOnly,
+    StatusFilesLoaded(PathBuf, Result<Vec<(char, String)>, String>),
+    ToggleDetailsPanel,
+    /// The details panel splitter was released at this panel width
+    DetailsPanelResized(i32),
+    DetailsLoaded(PathBuf, RepoDetails),
 }
 
-#[derive(Clone, Copy, PartialEq)]
+/// What a push would send from one repository, gathered before the confirmation.
+#[derive(Clone)]
+struct OutgoingSummary {
+    path: PathBuf,
+    /// First lines of the outgoing changesets, or the error from `hg outgoing`
+    changesets: Result<Vec<String>, String>,
+    /// Local secret changesets, which are never pushed
+    secret: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
 enum SortOrder {
+    #[default]
     Ascending,
     Descending,
-    None, // Default (usually Path Ascending)
 }
 
+/// Sort keys, most significant first. The path always breaks the remaining
+/// ties; with no keys the list is sorted by path only.
 struct SortState {
-    column: usize,
-    order: SortOrder,
+    keys: Vec<(usize, SortOrder)>, // Column index, order
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ColumnId {
+    Path,
+    Branch,
+    Rev,
+    Mod,
+    Phase,
+    Status,
+    Tag,
+    Author,
+    Date,
+    Hash,
+    Duration,
+}
+
+/// Static description of a browser column (header label, width and legend text).
+struct ColumnSpec {
+    id: ColumnId,
+    name: &'static str,
+    width: i32,
+    description: &'static str,
+    /// Shown until the user changes it in Preferences
+    visible_by_default: bool,
+}
+
+const LEGEND_BUTTON_WIDTH: i32 = 24;
+
+/// Narrowest a column can be dragged in the header.
+const MIN_COLUMN_WIDTH: i32 = 40;
+
+const COLUMNS: &[ColumnSpec] = &[
+    ColumnSpec {
+        id: ColumnId::Path,
+        name: "Path",
+        width: 450,
+        description: "Repository location (full path or folder name, see Preferences). Always shown.",
+        visible_by_default: true,
+    },
+    ColumnSpec {
+        id: ColumnId::Branch,
+        name: "Branch",
+        width: 150,
+        description: "Named branch of the working directory (hg branch).",
+        visible_by_default: true,
+    },
+    ColumnSpec {
+        id: ColumnId::Rev,
+        name: "Rev",
+        width: 140,
+        description: "Local revision number and short hash of the working directory parent (hg id), or the hash alone with Show hashes in Preferences. Its tags are shown in the row tooltip.",
+        visible_by_default: true,
+    },
+    ColumnSpec {
+        id: ColumnId::Mod,
+        name: "Mod",
+        width: 80,
+        description: "Yes when the working directory has uncommitted changes. Merge! when the current branch has more than one head.",
+        visible_by_default: true,
+    },
+    ColumnSpec {
+        id: ColumnId::Phase,
+        name: "Phase",
+        width: 100,
+        description: "Phase of the working directory parent: Public (pushed), Draft (local) or Secret (never pushed).",
+        visible_by_default: true,
+    },
+    ColumnSpec {
+        id: ColumnId::Status,
+        name: "Status",
+        width: 140 - LEGEND_BUTTON_WIDTH,
+        description: "Result of the last operation run from ManaHg.",
+        visible_by_default: true,
+    },
+    ColumnSpec {
+        id: ColumnId::Tag,
+        name: "Tag",
+        width: 100,
+        description: "Most recent tag reachable from the working directory parent (hidden by default, see Preferences).",
+        visible_by_default: false,
+    },
+    ColumnSpec {
+        id: ColumnId::Author,
+        name: "Author",
+        width: 120,
+        description: "Author of the working directory parent (hidden by default, see Preferences).",
+        visible_by_default: false,
+    },
+    ColumnSpec {
+        id: ColumnId::Date,
+        name: "Last Commit",
+        width: 110,
+        description: "How long ago the working directory parent was committed, with the exact date in the row tooltip (hidden by default, see Preferences).",
+        visible_by_default: false,
+    },
+    ColumnSpec {
+        id: ColumnId::Hash,
+        name: "Hash",
+        width: 110,
+        description: "Short changeset hash of the working directory parent, the same in every clone unlike Rev (hidden by default, see Preferences).",
+        visible_by_default: false,
+    },
+    ColumnSpec {
+        id: ColumnId::Duration,
+        name: "Duration",
+        width: 80,
+        description: "How long the last operation took on the repository, its refresh included (hidden by default, see Preferences).",
+        visible_by_default: false,
+    },
+];
+
+/// Whether `COLUMNS[idx]` is shown. Path always is.
+fn column_visible(prefs: &Preferences, idx: usize) -> bool {
+    COLUMNS[idx].id == ColumnId::Path
+        || prefs
+            .visible_columns
+            .get(idx)
+            .copied()
+            .unwrap_or(COLUMNS[idx].visible_by_default)
+}
+
+/// Indices into `COLUMNS` of the columns currently shown, in display order.
+fn visible_columns(prefs: &Preferences) -> Vec<usize> {
+    (0..COLUMNS.len()).filter(|&i| column_visible(prefs, i)).collect()
+}
+
+/// Widths of the visible columns: the dragged width or the default, with the
+/// last column stretched so the row fills `total` (less the legend button).
+fn layout_column_widths(prefs: &Preferences, total: i32) -> Vec<i32> {
+    let mut widths: Vec<i32> = visible_columns(prefs)
+        .into_iter()
+        .map(|i| {
+            let col = &COLUMNS[i];
+            prefs.column_widths.get(col.name).copied().unwrap_or(col.width).max(MIN_COLUMN_WIDTH)
+        })
+        .collect();
+    if let Some((last, others)) = widths.split_last_mut() {
+        let rest = total - LEGEND_BUTTON_WIDTH - others.iter().sum::<i32>();
+        *last = (*last).max(rest);
+    }
+    widths
 }
 //...
 const WIDGET_THEMES: &[(&str, Option<ThemeType>)] = &[
@@ -184,32 +1064,171 @@ const COLOR_THEMES: &[(&str, Option<&[ColorMap]>)] = &[
     ("Solarized Light", Some(&color_themes::fleet::SOLARIZED_LIGHT)),
 ];
 
+/// A menu bar entry. Its shortcut can be changed in the `shortcuts` section of
+/// the config, keyed by `action_name`.
+struct MenuAction {
+    path: &'static str,
+    /// Default shortcut, in the format read by `parse_shortcut`
+    shortcut: &'static str,
+    flag: MenuFlag,
+    message: Message,
+}
+
+/// Every menu bar entry that sends a message, in menu order.
+fn menu_actions(prefs: &Preferences) -> Vec<MenuAction> {
+    let toggle = |on: bool| if on { MenuFlag::Toggle | MenuFlag::Value } else { MenuFlag::Toggle };
+    let action = |path, shortcut, message| MenuAction { path, shortcut, flag: MenuFlag::Normal, message };
+    vec![
+        action("&File/Search for repos...\t", "Ctrl++", Message::AddFolder),
+        action("&File/Clone...\t", "", Message::OpenClone),
+        action("&File/New Profile...\t", "", Message::NewProfile),
+        action("&File/Remove\t", "Delete", Message::RemoveSelected),
+        MenuAction { flag: MenuFlag::Normal | MenuFlag::MenuDivider, ..action("&File/Remove Missing\t", "", Message::RemoveMissing) },
+        action("&File/Preferences...\t", "Ctrl+P", Message::OpenPreferences),
+        action("&File/Quit\t", "Ctrl+Q", Message::Quit),
+        // Actions menu
+        action("&Action/Open in TortoiseHg\t", "", Message::OpenTortoiseHg),
+        MenuAction { flag: MenuFlag::Normal | MenuFlag::MenuDivider, ..action("&Action/Open in VS Code\t", "", Message::OpenVsCode) },
+        action("&Action/Refresh\t", "F5", Message::Refresh),
+        // Shift+F5 is handled in the browser: a menu F5 shortcut also matches it with Shift held
+        action("&Action/Refresh All\t", "Ctrl+R", Message::RefreshAll),
+        action("&Action/Pull All Branches\t", "", Message::PullAll),
+        action("&Action/Pull Current Branch\t", "", Message::PullCurrent),
+        action("&Action/Pull with Subrepositories\t", "", Message::PullSubrepos),
+        action("&Action/Pull then Update\t", "", Message::PullAndUpdate),
+        action("&Action/Sync (Pull + Update + Merge)\t", "", Message::Sync),
+        action("&Action/Check Remotes\t", "", Message::CheckRemotes),
+        action("&Action/Retry Failed\t", "", Message::RetryFailed),
+        action("&Action/Push...\t", "", Message::Push),
+        action("&Action/Update to Latest\t", "", Message::UpdateLatest),
+        action("&Action/Update to Last Public\t", "", Message::UpdateLastPublic),
+        action("&Action/Force Update (discard changes)...\t", "", Message::OpenForceUpdate),
+        action("&Action/Update to Revision...\t", "", Message::OpenUpdateRev),
+        action("&Action/Switch Branch...\t", "", Message::OpenSwitchBranch),
+        action("&Action/New Branch...\t", "", Message::OpenNewBranch),
+        action("&Action/Update to Tag...\t", "", Message::OpenUpdateTag),
+        action("&Action/Tag...\t", "", Message::CreateTag),
+        action("&Action/Commit...\t", "", Message::Commit),
+        action("&Action/Change Phase...\t", "", Message::OpenChangePhase),
+        action("&Action/Show Diff...\t", "", Message::ShowDiff),
+        action("&Action/Show Graph...\t", "", Message::ShowGraph),
+        action("&Action/History...\t", "", Message::ShowHistory),
+        action("&Action/Resolve Conflicts...\t", "", Message::ShowConflicts),
+        action("&Action/Edit Remote Paths...\t", "", Message::OpenEditPaths),
+        action("&Action/Revert All Changes...\t", "", Message::OpenRevert),
+        action("&Action/Abort Interrupted Operation...\t", "", Message::AbortInterrupted),
+        action("&Action/Purge Untracked Files...\t", "", Message::OpenPurge),
+        action("&Action/Undo Last Commit or Pull...\t", "", Message::UndoLast),
+        action("&Edit/Copy", "Ctrl+C", Message::Copy),
+        action("&Edit/Copy Table", "Ctrl+Shift+C", Message::CopyTable),
+        action("&Edit/Copy Revision Hash", "", Message::CopyHash),
+        action("&Edit/Copy Branch Name", "", Message::CopyBranch),
+        MenuAction { flag: toggle(prefs.show_status_panel), ..action("&View/File Status Panel\t", "", Message::ToggleStatusPanel) },
+        MenuAction { flag: toggle(prefs.show_details_panel), ..action("&View/Details Panel\t", "", Message::ToggleDetailsPanel) },
+        action("&View/Output Log\t", "", Message::ShowOutputLog),
+        MenuAction { flag: toggle(prefs.modified_only), ..action("&View/Show Modified Only\t", "", Message::ToggleModifiedOnly) },
+        MenuAction { flag: toggle(prefs.group_by_parent), ..action("&View/Group by Parent Folder\t", "", Message::ToggleGroupByParent) },
+        action("&Selection/Select All", "Ctrl+A", Message::SelectAll),
+        action("&Selection/Invert Selection", "Ctrl+Shift+A", Message::InvertSelection),
+        action("&Selection/Select None", "", Message::SelectNone),
+        action("&Selection/Select Modified", "", Message::SelectModified),
+        action("&Selection/Select Matching...", "", Message::OpenSelectMatching),
+        action("&Selection/Select Errored", "Ctrl+E", Message::SelectErrored),
+        action("&Help/Legend\t", "F1", Message::ToggleLegend),
+        action("&Help/View Log\t", "", Message::ViewLog),
+    ]
+}
+
+/// Name of a menu entry in the config and the Preferences, e.g. "Action/Refresh".
+fn action_name(path: &str) -> String {
+    path.replace('&', "").trim_end_matches('\t').to_string()
+}
+
+/// The shortcut of `action` set in `custom`, else its default. A custom one
+/// that cannot be read is reported in `warnings` and the default is used.
+fn action_shortcut(action: &MenuAction, custom: &HashMap<String, String>, warnings: &mut Vec<String>) -> Shortcut {
+    let default = parse_shortcut(action.shortcut).unwrap_or(Shortcut::None);
+    let name = action_name(action.path);
+    match custom.get(&name) {
+        None => default,
+        Some(text) => parse_shortcut(text).unwrap_or_else(|| {
+            warnings.push(format!("Unknown shortcut '{}' for {}, using the default", text, name));
+            default
+        }),
+    }
+}
+
+/// Reads a shortcut such as "Ctrl+Shift+C", "F5", "Delete" or "Ctrl++". An empty
+/// text (or "None") is no shortcut; `None` when the text is not understood.
+fn parse_shortcut(text: &str) -> Option<Shortcut> {
+    let text = text.trim();
+    if text.is_empty() || text.eq_ignore_ascii_case("none") {
+        return Some(Shortcut::None);
+    }
+    // The key follows the last '+', which may be the key itself as in "Ctrl++"
+    let (modifiers, key) = match text.strip_suffix('+') {
+        Some(rest) if rest.is_empty() || rest.ends_with('+') => (rest.trim_end_matches('+'), "+"),
+        Some(_) => return None,
+        None => text.rsplit_once('+').unwrap_or(("", text)),
+    };
+    let mut shortcut = Shortcut::None;
+    for modifier in modifiers.split('+').map(str::trim).filter(|m| !m.is_empty()) {
+        shortcut |= match modifier.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => Shortcut::Ctrl,
+            "shift" => Shortcut::Shift,
+            "alt" => Shortcut::Alt,
+            "meta" | "cmd" | "command" => Shortcut::Meta,
+            _ => return None,
+        };
+    }
+
+    let key = key.trim();
+    let mut chars = key.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(shortcut | c.to_ascii_lowercase());
+    }
+    if let Some(n) = key.strip_prefix(['F', 'f']).and_then(|n| n.parse::<i32>().ok())
+        && (1..=12).contains(&n)
+    {
+        return Some(shortcut | Key::from_i32(Key::F1.bits() + n - 1));
+    }
+    let named = match key.to_ascii_lowercase().as_str() {
+        "delete" | "del" => Key::Delete,
+        "insert" | "ins" => Key::Insert,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "escape" | "esc" => Key::Escape,
+        "enter" | "return" => Key::Enter,
+        "tab" => Key::Tab,
+        "backspace" => Key::BackSpace,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "space" => return Some(shortcut | ' '),
+        _ => return None,
+    };
+    Some(shortcut | named)
+}
+
 fn main() {
+    if let Some(code) = cli::run(&std::env::args().collect::<Vec<_>>()) {
+        std::process::exit(code);
+    }
     let app = app::App::default();
 
     // Load config early
-    let config = Arc::new(Mutex::new(load_config()));
-    let initial_theme_idx = config.lock().unwrap().theme_idx;
-    let initial_scheme_idx = config.lock().unwrap().scheme_idx;
-    let initial_color_idx = config.lock().unwrap().color_idx;
-    let mut current_show_full_path = config.lock().unwrap().show_full_path;
+    let config = load_config();
+    let mut prefs = config.prefs.clone();
 
     // Apply saved themes/schemes in order
-    if initial_theme_idx < WIDGET_THEMES.len() {
-        if let Some(t) = WIDGET_THEMES[initial_theme_idx].1 {
-            WidgetTheme::new(t).apply();
-        }
-    }
-    if initial_scheme_idx < WIDGET_SCHEMES.len() {
-        if let Some(s) = WIDGET_SCHEMES[initial_scheme_idx].1 {
-            WidgetScheme::new(s).apply();
-        }
-    }
-    if initial_color_idx < COLOR_THEMES.len() {
-        if let Some(c) = COLOR_THEMES[initial_color_idx].1 {
-            ColorTheme::new(c).apply();
-        }
-    }
+    apply_theme(&prefs);
+    configure_worker_pool(prefs.max_parallelism);
+    repo::set_log_file(prefs.log_to_file.then(log_file));
+    repo::set_hg_env(config.env_vars.clone(), config.repo_env_vars.clone());
+    repo::set_network_retries(config.network_retries.unwrap_or(repo::DEFAULT_NETWORK_RETRIES));
 
     let mut wind = Window::default().with_size(1000, 750).with_label("ManaHg");
 
@@ -222,114 +1241,27 @@ fn main() {
 
     // Menu Bar
     let mut menu = SysMenuBar::new(0, 0, 1000, 30, "");
-    menu.add_emit(
-        "&File/Search for repos...\t",
-        Shortcut::Ctrl | '+',
-        MenuFlag::Normal,
-        s.clone(),
-        Message::AddFolder,
-    );
-    menu.add_emit(
-        "&File/Remove\t",
-        Shortcut::None | Key::Delete,
-        MenuFlag::Normal | MenuFlag::MenuDivider,
-        s.clone(),
-        Message::RemoveSelected,
-    );
-    menu.add_emit(
-        "&File/Preferences...\t",
-        Shortcut::Ctrl | 'p',
-        MenuFlag::Normal,
-        s.clone(),
-        Message::OpenPreferences,
-    );
-    menu.add(
-        "&File/Quit\t",
-        Shortcut::Ctrl | 'q',
-        MenuFlag::Normal,
-        |_| app::quit(),
-    );
-
-    // Actions menu
-    menu.add_emit(
-        "&Action/Open in TortoiseHg\t",
-        Shortcut::None,
-        MenuFlag::Normal | MenuFlag::MenuDivider,
-        s.clone(),
-        Message::OpenTortoiseHg
-    );
-    menu.add_emit(
-        "&Action/Refresh\t",
-        Shortcut::None | Key::F5,
-        MenuFlag::Normal,
-        s.clone(),
-        Message::Refresh,
-    );
-    menu.add_emit(
-        "&Action/Pull All Branches\t",
-        Shortcut::None,
-        MenuFlag::Normal,
-        s.clone(),
-        Message::PullAll,
-    );
-    menu.add_emit(
-        "&Action/Pull Current Branch\t",
-        Shortcut::None,
-        MenuFlag::Normal,
-        s.clone(),
-        Message::PullCurrent,
-    );
-    menu.add_emit(
-        "&Action/Update to Latest\t",
-        Shortcut::None,
-        MenuFlag::Normal,
-        s.clone(),
-        Message::UpdateLatest,
-    );
-    menu.add_emit(
-        "&Action/Switch Branch...\t",
-        Shortcut::None,
-        MenuFlag::Normal,
-        s.clone(),
-        Message::OpenSwitchBranch,
-    );
-    menu.add_emit(
-        "&Action/Update to Tag...\t",
-        Shortcut::None,
-        MenuFlag::Normal,
-        s.clone(),
-        Message::OpenUpdateTag,
-    );
-    menu.add_emit(
-        "&Action/Commit...\t",
-        Shortcut::None,
-        MenuFlag::Normal,
-        s.clone(),
-        Message::Commit,
-    );
-
-    menu.add_emit(
-        "&Edit/Copy",
-        Shortcut::Ctrl | 'c',
-        MenuFlag::Normal,
-        s.clone(),
-        Message::Copy,
-    );
-    menu.add_emit(
-        "&Selection/Select All",
-        Shortcut::Ctrl | 'a',
-        MenuFlag::Normal,
-        s.clone(),
-        Message::SelectAll,
-    );
-    menu.add("&Help/About", Shortcut::None, MenuFlag::Normal, |_| {
-        let mut help_win = Window::default().with_size(300, 180).with_label("About");
-        help_win.set_border(true); // Ensure decorations
-        let mut pack = Pack::new(10, 10, 280, 160, "");
-        pack.set_spacing(10);
-        let _frame = Frame::default()
-            .with_size(0, 80)
-            .with_label("ManaHg v0.1\nRust Implementation");
+    let mut shortcut_warnings = Vec::new();
+    let mut used: Vec<(Shortcut, String)> = Vec::new();
+    for action in menu_actions(&prefs) {
+        let shortcut = action_shortcut(&action, &config.shortcuts, &mut shortcut_warnings);
+        let name = action_name(action.path);
+        if shortcut != Shortcut::None {
+            if let Some((_, other)) = used.iter().find(|(u, _)| *u == shortcut) {
+                shortcut_warnings.push(format!("Shortcut of {} is also used by {}", name, other));
+            }
+            used.push((shortcut, name));
+        }
+        menu.add_emit(action.path, shortcut, action.flag, s.clone(), action.message);
+    }
+    menu.add("&Help/About", Shortcut::None, MenuFlag::Normal, |_| {
+        let mut help_win = Window::default().with_size(300, 180).with_label("About");
+        help_win.set_border(true); // Ensure decorations
+        let mut pack = Pack::new(10, 10, 280, 160, "");
+        pack.set_spacing(10);
+        let _frame = Frame::default()
+            .with_size(0, 80)
+            .with_label("ManaHg v0.1\nRust Implementation");
 
         let mut btn_close = Button::default().with_size(280, 30).with_label("Close");
         let mut win_c = help_win.clone();
@@ -346,26 +1278,103 @@ fn main() {
 
     // Actions menu removed from toolbar, now only in Menu Bar and Context Menu
 
+    // Profile selector
+    let mut profile_row = Flex::default().row();
+    let profile_label = Frame::default().with_label("Profile:");
+    profile_row.fixed(&profile_label, 60);
+    let mut profile_choice = fltk::menu::Choice::default();
+    profile_row.fixed(&profile_choice, 200);
+    Frame::default();
+    profile_row.end();
+    flex.fixed(&profile_row, 26);
+    fill_profile_choice(&mut profile_choice, &config, &prefs.active_profile);
+    let sender = s.clone();
+    profile_choice.set_callback(move |c| {
+        if let Some(name) = c.choice() {
+            sender.send(Message::SwitchProfile(name));
+        }
+    });
+
+    // Repository list on the left, details panel on the right
+    let mut main_row = Flex::default().row();
+    let mut list_col = Flex::default().column();
+
     // Header Row (Buttons)
-    let header_group = Group::default().with_size(1000, 24);
-    let col_widths = [450, 150, 80, 80, 100, 140]; // Total 1000
-    let col_names = ["Path", "Branch", "Rev", "Mod", "Phase", "Status"];
-    let mut x_off = 0;
-    for (i, &w) in col_widths.iter().enumerate() {
-        let mut btn = Button::new(x_off, 0, w, 24, col_names[i]);
-        btn.set_frame(FrameType::ThinUpBox);
-        btn.set_label_size(12);
-        btn.emit(s.clone(), Message::Sort(i));
-        x_off += w;
-    }
+    let mut header_group = Group::default().with_size(1000, 24);
     header_group.end();
-    flex.fixed(&header_group, 24);
+    // Buttons are laid out by build_header, not scaled with the group
+    header_group.make_resizable(false);
+    list_col.fixed(&header_group, 24);
+
+    let sender = s.clone();
+    header_group.resize_callback(move |_, _, _, _, _| sender.send(Message::LayoutColumns));
+
+    // Dragging the right edge of a column button resizes the column.
+    // Holds the dragged button's position, the press x and its width then.
+    let drag: Rc<Cell<Option<(i32, i32, i32)>>> = Rc::new(Cell::new(None));
+    let sender = s.clone();
+    header_group.handle(move |g, ev| {
+        // The legend button, always last, is not a column
+        let columns = g.children() - 1;
+        let edge_at = |x: i32| {
+            (0..columns).find(|&k| g.child(k).is_some_and(|c| (c.x() + c.w() - x).abs() <= 3))
+        };
+        match ev {
+            fltk::enums::Event::Move => {
+                let cursor = if edge_at(app::event_x()).is_some() {
+                    fltk::enums::Cursor::WE
+                } else {
+                    fltk::enums::Cursor::Default
+                };
+                fltk::draw::set_cursor(cursor);
+                false
+            }
+            fltk::enums::Event::Leave => {
+                fltk::draw::set_cursor(fltk::enums::Cursor::Default);
+                false
+            }
+            fltk::enums::Event::Push if app::event_button() == 1 => match edge_at(app::event_x()) {
+                Some(k) => {
+                    let w = g.child(k).map(|c| c.w()).unwrap_or_default();
+                    drag.set(Some((k, app::event_x(), w)));
+                    true
+                }
+                None => false,
+            },
+            fltk::enums::Event::Drag => match drag.get() {
+                Some((k, start_x, start_w)) => {
+                    let w = (start_w + app::event_x() - start_x).max(MIN_COLUMN_WIDTH);
+                    let mut x = g.child(k).map(|c| c.x()).unwrap_or_default();
+                    for j in k..g.children() {
+                        if let Some(mut c) = g.child(j) {
+                            let cw = if j == k { w } else { c.w() };
+                            c.resize(x, c.y(), cw, c.h());
+                            x += cw;
+                        }
+                    }
+                    g.redraw();
+                    sender.send(Message::ColumnResized(k as usize, w, false));
+                    true
+                }
+                None => false,
+            },
+            fltk::enums::Event::Released => match drag.take() {
+                Some((k, _, _)) => {
+                    let w = g.child(k).map(|c| c.w()).unwrap_or_default();
+                    sender.send(Message::ColumnResized(k as usize, w, true));
+                    fltk::draw::set_cursor(fltk::enums::Cursor::Default);
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    });
 
     // Repo List
     let mut browser = MultiBrowser::default();
 
     browser.set_column_char('\t');
-    browser.set_column_widths(&col_widths);
 
     browser.set_text_size(14);
     browser.set_type(fltk::browser::BrowserType::Multi);
@@ -374,16 +1383,108 @@ fn main() {
     let sender = s.clone();
     browser.set_callback(move |_| {
         if app::event_clicks() {
-            sender.send(Message::OpenTortoiseHg);
+            sender.send(Message::RowActivated);
+        } else {
+            sender.send(Message::SelectionChanged);
+        }
+    });
+    list_col.end();
+
+    // Details of the focused repo (View menu), resized by dragging the splitter
+    let mut splitter = Frame::default();
+    main_row.fixed(&splitter, 4);
+    let mut details_panel = fltk::text::TextDisplay::default();
+    details_panel.set_buffer(fltk::text::TextBuffer::default());
+    details_panel.set_highlight_data(fltk::text::TextBuffer::default(), status_style_table());
+    details_panel.wrap_mode(fltk::text::WrapMode::AtBounds, 0);
+    main_row.fixed(&details_panel, prefs.details_panel_width);
+    main_row.end();
+    if !prefs.show_details_panel {
+        splitter.hide();
+        details_panel.hide();
+    }
+    let mut details_path: Option<PathBuf> = None;
+
+    // Holds the press x and the panel width then
+    let split_drag: Rc<Cell<Option<(i32, i32)>>> = Rc::new(Cell::new(None));
+    let mut row_c = main_row.clone();
+    let panel_c = details_panel.clone();
+    let sender = s.clone();
+    splitter.handle(move |_, ev| match ev {
+        fltk::enums::Event::Enter | fltk::enums::Event::Move => {
+            fltk::draw::set_cursor(fltk::enums::Cursor::WE);
+            true
+        }
+        fltk::enums::Event::Leave => {
+            if split_drag.get().is_none() {
+                fltk::draw::set_cursor(fltk::enums::Cursor::Default);
+            }
+            true
+        }
+        fltk::enums::Event::Push => {
+            split_drag.set(Some((app::event_x(), panel_c.w())));
+            true
+        }
+        fltk::enums::Event::Drag => {
+            if let Some((start_x, start_w)) = split_drag.get() {
+                // Keep room for both the panel and the list
+                let w = (start_w - (app::event_x() - start_x)).clamp(150, (row_c.w() - 200).max(150));
+                row_c.fixed(&panel_c, w);
+                row_c.layout();
+                row_c.redraw();
+            }
+            true
+        }
+        fltk::enums::Event::Released => {
+            if split_drag.take().is_some() {
+                sender.send(Message::DetailsPanelResized(panel_c.w()));
+            }
+            fltk::draw::set_cursor(fltk::enums::Cursor::Default);
+            true
         }
+        _ => false,
     });
 
-    // Status Bar
+    // File status panel for the focused repo (View menu)
+    let mut status_panel = fltk::text::TextDisplay::default();
+    status_panel.set_buffer(fltk::text::TextBuffer::default());
+    status_panel.set_highlight_data(fltk::text::TextBuffer::default(), status_style_table());
+    flex.fixed(&status_panel, 150);
+    if !prefs.show_status_panel {
+        status_panel.hide();
+    }
+    let mut status_panel_path: Option<PathBuf> = None;
+
+    // Status Bar, with the progress of running batches on its right
+    let mut status_row = Flex::default().row();
     let mut status_bar = Frame::default().with_label("Ready");
     status_bar.set_frame(FrameType::FlatBox);
     status_bar.set_align(fltk::enums::Align::Left | fltk::enums::Align::Inside);
     status_bar.set_label_color(Color::Gray0);
-    flex.fixed(&status_bar, 24);
+    let mut summary_counts = Frame::default().with_id(SUMMARY_COUNTS_ID);
+    summary_counts.set_align(fltk::enums::Align::Right | fltk::enums::Align::Inside);
+    summary_counts.set_label_size(12);
+    status_row.fixed(&summary_counts, 280);
+    let mut summary_errors = Button::default().with_id(SUMMARY_ERRORS_ID);
+    summary_errors.set_frame(FrameType::FlatBox);
+    summary_errors.set_label_size(12);
+    summary_errors.set_tooltip("Select the repositories whose last operation failed");
+    summary_errors.emit(s.clone(), Message::SelectErrored);
+    status_row.fixed(&summary_errors, 80);
+    let mut progress = fltk::misc::Progress::default();
+    progress.set_selection_color(Color::from_rgb(0, 120, 215));
+    progress.set_label_size(12);
+    status_row.fixed(&progress, 200);
+    let mut btn_cancel = Button::default().with_label("Cancel");
+    btn_cancel.set_label_size(12);
+    btn_cancel.set_tooltip("Skip the repositories not started yet and stop the hg commands running");
+    btn_cancel.emit(s.clone(), Message::CancelBatches);
+    status_row.fixed(&btn_cancel, 70);
+    status_row.end();
+    progress.hide();
+    btn_cancel.hide();
+    flex.fixed(&status_row, 24);
+    let (mut progress_done, mut progress_total) = (0, 0);
 
     flex.end();
 
@@ -398,13 +1499,27 @@ fn main() {
         s.clone(),
         Message::RemoveSelected,
     );
+    popup_menu.add_emit(
+        "Show Error Details",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::ShowErrorDetails,
+    );
     popup_menu.add_emit(
         "Open in TortoiseHg",
         Shortcut::None,
-        MenuFlag::Normal | MenuFlag::MenuDivider,
+        MenuFlag::Normal,
         s.clone(),
         Message::OpenTortoiseHg,
     );
+    popup_menu.add_emit(
+        "Open in VS Code",
+        Shortcut::None,
+        MenuFlag::Normal | MenuFlag::MenuDivider,
+        s.clone(),
+        Message::OpenVsCode,
+    );
     popup_menu.add_emit(
         "Refresh",
         Shortcut::None,
@@ -412,6 +1527,13 @@ fn main() {
         s.clone(),
         Message::Refresh,
     );
+    popup_menu.add_emit(
+        "Refresh All",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::RefreshAll,
+    );
     popup_menu.add_emit(
         "Pull All Branches",
         Shortcut::None,
@@ -426,6 +1548,34 @@ fn main() {
         s.clone(),
         Message::PullCurrent,
     );
+    popup_menu.add_emit(
+        "Pull then Update",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::PullAndUpdate,
+    );
+    popup_menu.add_emit(
+        "Sync (Pull + Update + Merge)",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::Sync,
+    );
+    popup_menu.add_emit(
+        "Check Remotes",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::CheckRemotes,
+    );
+    popup_menu.add_emit(
+        "Push...",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::Push,
+    );
     popup_menu.add_emit(
         "Update to Latest",
         Shortcut::None,
@@ -433,6 +1583,27 @@ fn main() {
         s.clone(),
         Message::UpdateLatest,
     );
+    popup_menu.add_emit(
+        "Update to Last Public",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::UpdateLastPublic,
+    );
+    popup_menu.add_emit(
+        "Force Update (discard changes)...",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::OpenForceUpdate,
+    );
+    popup_menu.add_emit(
+        "Update to Revision...",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::OpenUpdateRev,
+    );
     popup_menu.add_emit(
         "Update to Tag...",
         Shortcut::None,
@@ -440,6 +1611,13 @@ fn main() {
         s.clone(),
         Message::OpenUpdateTag,
     );
+    popup_menu.add_emit(
+        "Tag...\t",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::CreateTag,
+    );
     popup_menu.add_emit(
         "Switch Branch...\t",
         Shortcut::None,
@@ -447,13 +1625,90 @@ fn main() {
         s.clone(),
         Message::OpenSwitchBranch,
     );
+    popup_menu.add_emit(
+        "New Branch...\t",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::OpenNewBranch,
+    );
     popup_menu.add_emit(
         "Commit...\t",
         Shortcut::None,
-        MenuFlag::Normal | MenuFlag::MenuDivider,
+        MenuFlag::Normal,
         s.clone(),
         Message::Commit,
     );
+    popup_menu.add_emit(
+        "Change Phase...\t",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::OpenChangePhase,
+    );
+    popup_menu.add_emit(
+        "Show Diff...\t",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::ShowDiff,
+    );
+    popup_menu.add_emit(
+        "Show Graph...\t",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::ShowGraph,
+    );
+    popup_menu.add_emit(
+        "History...\t",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::ShowHistory,
+    );
+    popup_menu.add_emit(
+        "Resolve Conflicts...\t",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::ShowConflicts,
+    );
+    popup_menu.add_emit(
+        "Edit Remote Paths...\t",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::OpenEditPaths,
+    );
+    popup_menu.add_emit(
+        "Edit .hgignore...",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::OpenEditIgnore,
+    );
+    popup_menu.add_emit(
+        "Revert All Changes...\t",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::OpenRevert,
+    );
+    popup_menu.add_emit(
+        "Abort Interrupted Operation...",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::AbortInterrupted,
+    );
+    popup_menu.add_emit(
+        "Purge Untracked Files...",
+        Shortcut::None,
+        MenuFlag::Normal | MenuFlag::MenuDivider,
+        s.clone(),
+        Message::OpenPurge,
+    );
     popup_menu.add_emit(
         "Copy",
         Shortcut::None,
@@ -461,9 +1716,86 @@ fn main() {
         s.clone(),
         Message::Copy,
     );
+    popup_menu.add_emit(
+        "Copy Table",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::CopyTable,
+    );
+    popup_menu.add_emit(
+        "Copy Revision Hash",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::CopyHash,
+    );
+    popup_menu.add_emit(
+        "Copy Branch Name",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::CopyBranch,
+    );
+    popup_menu.add_emit(
+        "Rename...",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::Rename,
+    );
+    popup_menu.add_emit(
+        "Locate...",
+        Shortcut::None,
+        MenuFlag::Normal,
+        s.clone(),
+        Message::Locate,
+    );
 
     let popup_menu_c = popup_menu.clone();
+    let key_sender = s.clone();
+    // Text typed so far for type-ahead find, and when the last key was typed
+    let typed: RefCell<(String, Instant)> = RefCell::new((String::new(), Instant::now()));
     browser.handle(move |_b, ev| {
+        if ev == fltk::enums::Event::KeyDown {
+            let key = app::event_key();
+            let state = app::event_state();
+            let shift_f5 = key == Key::F5 && state.contains(fltk::enums::EventState::Shift);
+            if shift_f5 {
+                key_sender.send(Message::RefreshAll);
+                return true;
+            }
+
+            // Printable keys without Ctrl, Alt or Meta jump to a repository by name
+            let text = app::event_text();
+            let modifiers = fltk::enums::EventState::Ctrl | fltk::enums::EventState::Alt | fltk::enums::EventState::Meta;
+            if !state.intersects(modifiers) && !text.is_empty() && !text.chars().any(char::is_control) {
+                let mut typed = typed.borrow_mut();
+                if typed.1.elapsed() > TYPE_AHEAD_TIMEOUT {
+                    typed.0.clear();
+                }
+                typed.0.push_str(&text);
+                typed.1 = Instant::now();
+                key_sender.send(Message::TypeAhead(typed.0.clone()));
+                return true;
+            }
+        }
+
+        // Folders dragged from the file manager arrive as a Paste after DndRelease
+        match ev {
+            fltk::enums::Event::DndEnter
+            | fltk::enums::Event::DndDrag
+            | fltk::enums::Event::DndRelease => return true,
+            fltk::enums::Event::Paste => {
+                let dirs = parse_dropped_paths(&app::event_text());
+                if !dirs.is_empty() {
+                    key_sender.send(Message::ScanFolders(dirs));
+                }
+                return true;
+            }
+            _ => {}
+        }
+
         // Handle Right Click (Button 3)
         // We capture both Push and Released to ensure no default processing happens
         if app::event_button() == 3 {
@@ -488,32 +1820,63 @@ fn main() {
 
     // Resize handling
     wind.resizable(&flex);
+    if let Some(g) = &config.window {
+        let (x, y, w, h) = clamp_to_work_area(g);
+        wind.resize(x, y, w, h);
+    }
     wind.show();
+    if config.window.is_some_and(|g| g.maximized) {
+        wind.maximize();
+    }
+
+    // Saved once the window has been still for a second, not on every step of a drag
+    let save_timeout: Rc<Cell<Option<app::TimeoutHandle>>> = Rc::new(Cell::new(None));
+    let sender = s.clone();
+    wind.resize_callback(move |_, _, _, _, _| {
+        if let Some(handle) = save_timeout.take() {
+            app::remove_timeout3(handle);
+        }
+        save_timeout.set(Some(app::add_timeout3(1.0, move |_| sender.send(Message::SaveWindowGeometry))));
+    });
+    let sender = s.clone();
+    wind.set_callback(move |_| {
+        // FLTK closes a window on Escape; only the close button should hide or quit
+        if app::event() == fltk::enums::Event::Shortcut && app::event_key() == Key::Escape {
+            return;
+        }
+        sender.send(Message::CloseWindow)
+    });
+    let tray_installed = tray::install(&wind, s.clone());
 
     let app_state: Arc<Mutex<Vec<Repository>>> = Arc::new(Mutex::new(Vec::new()));
+    let saved_sort = config
+        .sort_column
+        .as_deref()
+        .and_then(|name| COLUMNS.iter().position(|c| c.name == name))
+        .filter(|&c| column_visible(&prefs, c));
     let sort_state = Arc::new(Mutex::new(SortState {
-        column: 0,
-        order: SortOrder::None,
+        keys: saved_sort.map(|c| (c, config.sort_order)).into_iter().collect(),
     }));
+    build_header(&mut header_group, &prefs, &sort_state.lock().unwrap(), s.clone());
 
     // Callbacks
     // Buttons removed, so we don't need these emits anymore.
     // Menu items emit messages directly.
 
-    let cloned_repos = config.lock().unwrap().repositories.clone();
+    let cloned_repos = config.repositories.clone();
 
     // Load saved repositories immediately (fast, no refresh)
     {
         let mut repos = app_state.lock().unwrap();
         for p in &cloned_repos {
-            repos.push(Repository::new(p.clone()));
+            let mut r = Repository::new(p.clone());
+            r.alias = config.aliases.get(p).cloned();
+            apply_repo_prefs(&mut r, &prefs);
+            repos.push(r);
         }
+        sort_repos(&mut repos, &sort_state.lock().unwrap(), &prefs);
     }
-    update_browser(
-        &mut browser,
-        &app_state.lock().unwrap(),
-        current_show_full_path,
-    );
+    update_browser(&mut browser, &app_state.lock().unwrap(), &prefs, &HashSet::new());
 
     if !cloned_repos.is_empty() {
         // Trigger background refresh
@@ -523,9 +1886,28 @@ fn main() {
         });
     }
 
-    let mut current_theme_idx = initial_theme_idx;
-    let mut current_scheme_idx = initial_scheme_idx;
-    let mut current_color_idx = initial_color_idx;
+    let mut legend_win: Option<Window> = None;
+    // Repos where the last batch that made transactions made one, with what
+    // `hg rollback` undoes there; set once the batch is done
+    let mut undoable: Option<Vec<(PathBuf, &str)>> = None;
+    let mut graph_view: Option<GraphView> = None;
+    let mut history_view: Option<HistoryView> = None;
+    let mut resolve_view: Option<ResolveView> = None;
+    // Last Select Matching pattern and case sensitivity, for this session only
+    let mut last_match = (String::new(), false);
+    // Repository writes waiting for the next `FlushRepoWrites`, in arrival order
+    let mut pending_writes: Vec<RepoWrite> = Vec::new();
+
+    // Every hg command is recorded, so the log has history when first opened
+    let mut output_log = fltk::text::TextBuffer::default();
+    let mut output_log_view: Option<(Window, fltk::text::TextDisplay)> = None;
+    let sender = s.clone();
+    repo::set_log_sink(move |text| sender.send(Message::LogLine(text)));
+    for warning in shortcut_warnings {
+        eprintln!("{}", warning);
+        s.send(Message::LogLine(format!("{}\n", warning)));
+    }
+
 
     // Initial check: if args, scan them
     let args: Vec<String> = std::env::args().collect();
@@ -538,9 +1920,10 @@ fn main() {
         }
         if !dirs.is_empty() {
             let sender = s.clone();
+            let scan_prefs = prefs.clone();
             status_bar.set_label("Scanning...");
-            thread::spawn(move || {
-                scan_repositories(dirs, sender);
+            spawn_worker(move || {
+                scan_repositories(dirs, scan_prefs, sender);
             });
         }
     }
@@ -549,115 +1932,298 @@ fn main() {
     while app.wait() {
         if let Some(msg) = r.recv() {
             match msg {
+                Message::SaveWindowGeometry => save_window_geometry(&wind),
+                Message::Quit => {
+                    save_window_geometry(&wind);
+                    tray::remove();
+                    app::quit();
+                }
+                Message::CloseWindow => {
+                    if prefs.minimize_to_tray && tray_installed {
+                        tray::hide_window(&wind);
+                    } else {
+                        s.send(Message::Quit);
+                    }
+                }
+                Message::ToggleWindow => tray::toggle_window(&wind),
                 Message::AddFolder => {
                     let mut dialog =
                         dialog::NativeFileChooser::new(dialog::NativeFileChooserType::BrowseDir);
+                    if let Some(dir) = load_config().last_scan_dir {
+                        let _ = dialog.set_directory(&dir);
+                    }
                     dialog.show();
                     if !dialog.filename().as_os_str().is_empty() {
                         let path = dialog.filename();
+                        save_last_dir(|c| &mut c.last_scan_dir, &path);
                         let sender = s.clone();
+                        let scan_prefs = prefs.clone();
                         status_bar.set_label(&format!("Scanning {}...", path.display()));
-                        thread::spawn(move || {
-                            scan_repositories(vec![path], sender);
+                        spawn_worker(move || {
+                            scan_repositories(vec![path], scan_prefs, sender);
                         });
                     }
                 }
-                Message::ScanComplete(new_repos) => {
-                    let mut repos = app_state.lock().unwrap();
-                    for nr in new_repos {
-                        if !repos.iter().any(|r| r.path == nr.path) {
-                            repos.push(nr);
+                Message::OpenClone => {
+                    let mut win = Window::default().with_size(500, 170).with_label("Clone Repository");
+                    win.set_border(true);
+                    let mut flex = Flex::new(10, 10, 480, 150, "").column();
+                    flex.set_spacing(5);
+
+                    let url_label = Frame::default().with_label("Source URL:");
+                    flex.fixed(&url_label, 20);
+                    let url_input = fltk::input::Input::default();
+                    flex.fixed(&url_input, 25);
+
+                    let dest_label = Frame::default().with_label("Destination directory:");
+                    flex.fixed(&dest_label, 20);
+                    let mut dest_row = Flex::default().row();
+                    let dest_input = fltk::input::Input::default();
+                    let mut btn_browse = Button::default().with_label("Browse...");
+                    dest_row.fixed(&btn_browse, 80);
+                    dest_row.end();
+                    flex.fixed(&dest_row, 25);
+
+                    let btn_row = Flex::default().row();
+                    let mut btn_cancel = Button::default().with_label("Cancel");
+                    let mut btn_ok = Button::default().with_label("Clone");
+                    btn_row.end();
+                    flex.fixed(&btn_row, 30);
+
+                    flex.end();
+                    win.end();
+                    win.make_modal(true);
+                    win.show();
+
+                    // The chosen folder gets a subdirectory named after the source, as hg clone does
+                    let url_c = url_input.clone();
+                    let mut dest_c = dest_input.clone();
+                    btn_browse.set_callback(move |_| {
+                        let mut chooser =
+                            dialog::NativeFileChooser::new(dialog::NativeFileChooserType::BrowseDir);
+                        if let Some(dir) = load_config().last_clone_dir {
+                            let _ = chooser.set_directory(&dir);
                         }
-                    }
-                    repos.sort_by(|a, b| a.path.cmp(&b.path));
+                        chooser.show();
+                        let dir = chooser.filename();
+                        if dir.as_os_str().is_empty() {
+                            return;
+                        }
+                        save_last_dir(|c| &mut c.last_clone_dir, &dir);
+                        let url = url_c.value();
+                        let name = url.trim_end_matches(['/', '\\']).rsplit(['/', '\\']).next().unwrap_or("");
+                        let dest = if name.is_empty() { dir } else { dir.join(name) };
+                        dest_c.set_value(&dest.display().to_string());
+                    });
 
-                    save_config(&repos, current_theme_idx, current_scheme_idx, current_color_idx, current_show_full_path);
+                    let mut w_c = win.clone();
+                    btn_cancel.set_callback(move |_| w_c.hide());
 
-                    update_browser(&mut browser, &repos, current_show_full_path);
-                    status_bar.set_label(&format!("Found {} repositories", repos.len()));
-                }
-                Message::RepoUpdated(updated_repo) => {
-                    let mut repos = app_state.lock().unwrap();
-                    if let Some(r) = repos.iter_mut().find(|r| r.path == updated_repo.path) {
-                        // Preserve status if not set in updated_repo
-                        let old_status = r.last_status.clone();
-                        *r = updated_repo;
-                        if r.last_status.is_empty() {
-                            r.last_status = old_status;
+                    let sender = s.clone();
+                    let mut w_c2 = win.clone();
+                    btn_ok.set_callback(move |_| {
+                        let url = url_input.value().trim().to_string();
+                        let dest = dest_input.value().trim().to_string();
+                        if url.is_empty() || dest.is_empty() {
+                            dialog::alert(200, 200, "Enter a source URL and a destination directory.");
+                            return;
                         }
-                    }
-                    update_browser(&mut browser, &repos, current_show_full_path);
+                        let dest = PathBuf::from(dest);
+                        if dest.exists() {
+                            dialog::alert(200, 200, &format!("{} already exists.", dest.display()));
+                            return;
+                        }
+                        sender.send(Message::DoClone(url, dest));
+                        w_c2.hide();
+                    });
                 }
-                Message::SetStatus(path, status_msg) => {
+                Message::DoClone(url, dest) => {
+                    let sender = s;
+                    if refuse_when_queue_full(&[], sender) {
+                        continue;
+                    }
+                    status_bar.set_label(&format!("Cloning {}...", url));
+                    queue_batch(move || {
+                        let result = Repository::clone_repo(&url, &dest, repo::HG).map_err(|e| e.to_string());
+                        sender.send(Message::CloneFinished(result));
+                    });
+                }
+                Message::CloneFinished(result) => {
+                    let mut new_repo = match result {
+                        Ok(r) => r,
+                        Err(e) => {
+                            status_bar.set_label("Clone failed");
+                            dialog::alert(200, 200, &e);
+                            continue;
+                        }
+                    };
+                    let mut repos = app_state.lock().unwrap();
+                    status_bar.set_label(&format!("Cloned {}", new_repo.path.display()));
+                    if repos.iter().any(|r| r.path == new_repo.path) {
+                        continue;
+                    }
+                    let selection = selected_paths(&browser, &repos, &prefs);
+                    apply_repo_prefs(&mut new_repo, &prefs);
+                    new_repo.last_status = "Cloned".to_string();
+                    repos.push(new_repo);
+                    sort_repos(&mut repos, &sort_state.lock().unwrap(), &prefs);
+                    save_config(&repos, &prefs);
+                    update_browser(&mut browser, &repos, &prefs, &selection);
+                }
+                Message::SwitchProfile(name) => {
+                    if name == prefs.active_profile {
+                        continue;
+                    }
+                    let mut repos = app_state.lock().unwrap();
+                    save_config(&repos, &prefs);
+                    let config = load_config();
+                    prefs.active_profile = name;
+                    *repos = config
+                        .profiles
+                        .get(&prefs.active_profile)
+                        .into_iter()
+                        .flatten()
+                        .map(|p| {
+                            let mut r = Repository::new(p.clone());
+                            r.alias = config.aliases.get(p).cloned();
+                            apply_repo_prefs(&mut r, &prefs);
+                            r
+                        })
+                        .collect();
+                    sort_repos(&mut repos, &sort_state.lock().unwrap(), &prefs);
+                    save_config(&repos, &prefs);
+                    // A different list: start at the top with nothing selected
+                    browser.set_position(0);
+                    update_browser(&mut browser, &repos, &prefs, &HashSet::new());
+                    status_bar.set_label(&format!("Profile {}", prefs.active_profile));
+                    if !repos.is_empty() {
+                        s.send(Message::RefreshAll);
+                    }
+                }
+                Message::NewProfile => {
+                    let Some(name) = dialog::input_default("Name of the new profile:", "") else {
+                        continue;
+                    };
+                    let name = name.trim().to_string();
+                    // Choice menus treat these characters as menu syntax
+                    if name.is_empty() || name.contains(['/', '\\', '|', '&', '_']) {
+                        dialog::alert(200, 200, "Profile names cannot be empty or contain / \\ | & _");
+                        continue;
+                    }
+                    // Switching to a name with no saved list starts it empty
+                    fill_profile_choice(&mut profile_choice, &load_config(), &name);
+                    s.send(Message::SwitchProfile(name));
+                }
+                Message::ScanFolders(dirs) => {
+                    let label = match dirs.as_slice() {
+                        [dir] => format!("Scanning {}...", dir.display()),
+                        _ => format!("Scanning {} folders...", dirs.len()),
+                    };
+                    status_bar.set_label(&label);
+                    let sender = s.clone();
+                    let scan_prefs = prefs.clone();
+                    spawn_worker(move || {
+                        scan_repositories(dirs, scan_prefs, sender);
+                    });
+                }
+                Message::RepoDiscovered(mut new_repo) => {
+                    let mut repos = app_state.lock().unwrap();
+                    // Repos already in the list keep their state and alias
+                    if repos.iter().any(|r| r.path == new_repo.path) {
+                        continue;
+                    }
+                    let selection = selected_paths(&browser, &repos, &prefs);
+                    apply_repo_prefs(&mut new_repo, &prefs);
+                    repos.push(new_repo);
+                    sort_repos(&mut repos, &sort_state.lock().unwrap(), &prefs);
+                    update_browser(&mut browser, &repos, &prefs, &selection);
+                }
+                Message::ScanComplete(found) => {
+                    save_config(&app_state.lock().unwrap(), &prefs);
+                    status_bar.set_label(&format!("Found {} repositories", found));
+                }
+                Message::RepoUpdated(updated_repo) => {
+                    queue_repo_write(&mut pending_writes, RepoWrite::Replace(Box::new(updated_repo)), s.clone());
+                }
+                Message::SetStatus(path, status_msg) => {
+                    queue_repo_write(&mut pending_writes, RepoWrite::Status(path, status_msg), s.clone());
+                }
+                Message::FlushRepoWrites => {
                     let mut repos = app_state.lock().unwrap();
-                    if let Some(r) = repos.iter_mut().find(|r| r.path == path) {
-                        r.last_status = status_msg;
+                    let selection = selected_paths(&browser, &repos, &prefs);
+                    let replaced = apply_repo_writes(&mut repos, std::mem::take(&mut pending_writes), &prefs);
+                    // The new state may move the rows under the current sort
+                    sort_repos(&mut repos, &sort_state.lock().unwrap(), &prefs);
+                    update_browser(&mut browser, &repos, &prefs, &selection);
+
+                    // Operations refresh the repo when done, so reload its details too
+                    if prefs.show_details_panel
+                        && let Some(path) = details_path.as_ref().filter(|p| replaced.contains(*p))
+                        && let Some(r) = repos.iter().find(|r| &r.path == path)
+                    {
+                        load_repo_details(r.clone(), s.clone());
+                    }
+
+                    // Operations refresh the repo when done, so reload its file list too
+                    if prefs.show_status_panel
+                        && let Some(path) = status_panel_path.as_ref().filter(|p| replaced.contains(*p))
+                        && let Some(r) = repos.iter().find(|r| &r.path == path)
+                    {
+                        load_status_files(r.clone(), s.clone());
                     }
-                    update_browser(&mut browser, &repos, current_show_full_path);
                 }
-                Message::Sort(col) => {
+                Message::Sort(col, secondary) => {
                     let mut state = sort_state.lock().unwrap();
-                    if state.column == col {
-                        match state.order {
-                            SortOrder::None => state.order = SortOrder::Ascending,
-                            SortOrder::Ascending => state.order = SortOrder::Descending,
-                            SortOrder::Descending => state.order = SortOrder::None,
+                    // A plain click sorts by that column alone; Shift-click adds it
+                    // after the current keys. Either way a column cycles
+                    // Ascending, Descending, then off.
+                    if !secondary {
+                        state.keys.retain(|&(c, _)| c == col);
+                    }
+                    match state.keys.iter().position(|&(c, _)| c == col) {
+                        Some(pos) if state.keys[pos].1 == SortOrder::Ascending => {
+                            state.keys[pos].1 = SortOrder::Descending;
                         }
-                    } else {
-                        state.column = col;
-                        state.order = SortOrder::Ascending;
-                    }
-
-                    // Update header labels
-                    let col_names = ["Path", "Branch", "Rev", "Mod", "Phase", "Status"];
-                    for i in 0..col_names.len() {
-                        if let Some(mut widget) = header_group.child(i as i32) {
-                            let mut label = col_names[i].to_string();
-                            if i == state.column {
-                                match state.order {
-                                    SortOrder::Ascending => label.push_str(" ▲"),
-                                    SortOrder::Descending => label.push_str(" ▼"),
-                                    SortOrder::None => {}
-                                }
-                            }
-                            widget.set_label(&label);
-                            widget.redraw();
+                        Some(pos) => {
+                            state.keys.remove(pos);
                         }
+                        None => state.keys.push((col, SortOrder::Ascending)),
                     }
 
+                    build_header(&mut header_group, &prefs, &state, s.clone());
+
                     let mut repos = app_state.lock().unwrap();
-                    sort_repos(&mut repos, &state);
-                    update_browser(&mut browser, &repos, current_show_full_path);
+                    let selection = selected_paths(&browser, &repos, &prefs);
+                    sort_repos(&mut repos, &state, &prefs);
+                    update_browser(&mut browser, &repos, &prefs, &selection);
+                    save_sort(&state);
+                }
+                Message::ColumnResized(pos, width, done) => {
+                    let Some(&i) = visible_columns(&prefs).get(pos) else {
+                        continue;
+                    };
+                    prefs.column_widths.insert(COLUMNS[i].name.to_string(), width);
+                    browser.set_column_widths(&layout_column_widths(&prefs, browser.w()));
+                    browser.redraw();
+                    if done {
+                        // Lets the last column take up any space given back
+                        build_header(&mut header_group, &prefs, &sort_state.lock().unwrap(), s.clone());
+                        save_config(&app_state.lock().unwrap(), &prefs);
+                    }
+                }
+                Message::LayoutColumns => {
+                    build_header(&mut header_group, &prefs, &sort_state.lock().unwrap(), s.clone());
+                    browser.set_column_widths(&layout_column_widths(&prefs, browser.w()));
+                    browser.redraw();
                 }
                 Message::Refresh => {
-                    let selected_repos = get_selected_repos(&browser, &app_state.lock().unwrap());
+                    let selected_repos = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
                     if selected_repos.is_empty() {
                         status_bar.set_label("Select repositories to refresh.");
                         continue;
                     }
                     status_bar.set_label("Refreshing selected...");
-                    let sender = s.clone();
-
-                    {
-                        let mut repos = app_state.lock().unwrap();
-                        for r in repos.iter_mut() {
-                            if selected_repos.iter().any(|sel| sel.path == r.path) {
-                                r.last_status = "Refreshing...".to_string();
-                            }
-                        }
-                        update_browser(&mut browser, &repos, current_show_full_path);
-                    }
-
-                    thread::spawn(move || {
-                        selected_repos.par_iter().for_each(|r| {
-                            let mut r = r.clone();
-                            r.refresh();
-                            r.last_status = "Ready".to_string();
-                            sender.send(Message::RepoUpdated(r));
-                        });
-
-                        sender.send(Message::SetGlobalStatus("Ready".into()));
-                    });
+                    spawn_refresh(selected_repos, s);
                 }
                 Message::RefreshAll => {
                     let repos_clone = app_state.lock().unwrap().clone();
@@ -666,49 +2232,67 @@ fn main() {
                         continue;
                     }
                     status_bar.set_label("Refreshing all...");
-                    let sender = s.clone();
-
-                    {
-                        let mut repos = app_state.lock().unwrap();
-                        for r in repos.iter_mut() {
-                            r.last_status = "Refreshing...".to_string();
-                        }
-                        update_browser(&mut browser, &repos, current_show_full_path);
-                    }
-
-                    thread::spawn(move || {
-                        repos_clone.par_iter().for_each(|r: &Repository| {
-                            let mut r = r.clone();
-                            r.refresh();
-                            r.last_status = "Ready".to_string();
-                            sender.send(Message::RepoUpdated(r));
-                        });
-
-                        sender.send(Message::SetGlobalStatus("Ready".into()));
-                    });
+                    spawn_refresh(repos_clone, s);
                 }
                 Message::RemoveSelected => {
-                    let selected = get_selected_repos(&browser, &app_state.lock().unwrap());
+                    let selected = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
                     if selected.is_empty() {
                         continue;
                     }
 
+                    if prefs.confirm_remove {
+                        let question = format!(
+                            "Remove {} repositories from the list?\n(Your working copies are not deleted, they are only removed from ManaHg.)",
+                            selected.len()
+                        );
+                        if dialog::choice2(200, 200, &question, "Cancel", "Remove", "") != Some(1) {
+                            continue;
+                        }
+                    }
+
                     let mut repos = app_state.lock().unwrap();
                     let len_before = repos.len();
                     repos.retain(|r| !selected.iter().any(|sel| sel.path == r.path));
 
                     if repos.len() != len_before {
-                        save_config(&repos, current_theme_idx, current_scheme_idx, current_color_idx, current_show_full_path);
-                        update_browser(&mut browser, &repos, current_show_full_path);
+                        save_config(&repos, &prefs);
+                        update_browser(&mut browser, &repos, &prefs, &HashSet::new());
+                    }
+                }
+                Message::RemoveMissing => {
+                    let mut repos = app_state.lock().unwrap();
+                    let missing = repos.iter().filter(|r| r.missing).count();
+                    if missing == 0 {
+                        status_bar.set_label("No missing repositories");
+                        continue;
+                    }
+
+                    if prefs.confirm_remove {
+                        let question = format!(
+                            "Remove {} repositories whose folder was not found from the list?",
+                            missing
+                        );
+                        if dialog::choice2(200, 200, &question, "Cancel", "Remove", "") != Some(1) {
+                            continue;
+                        }
                     }
+
+                    let selection = selected_paths(&browser, &repos, &prefs);
+                    repos.retain(|r| !r.missing);
+                    save_config(&repos, &prefs);
+                    update_browser(&mut browser, &repos, &prefs, &selection);
+                    status_bar.set_label(&format!("Removed {} missing repositories", missing));
                 }
                 Message::OpenPreferences => {
                     let mut prefs_win = Window::default()
-                        .with_size(300, 350)
+                        .with_size(300, 600)
                         .with_label("Preferences");
                     prefs_win.set_border(true);
-                    let mut pack = Pack::new(10, 10, 280, 330, "");
-                    pack.set_spacing(10);
+                    let tabs = fltk::group::Tabs::new(5, 5, 290, 590, "");
+                    let general_tab = Group::new(5, 30, 290, 565, "General");
+                    let general_scroll = fltk::group::Scroll::new(10, 40, 280, 505, "");
+                    let mut pack = Pack::new(10, 40, 260, 0, "");
+                    pack.set_spacing(8);
 
                     // Widget Theme
                     pack.add(
@@ -720,7 +2304,9 @@ fn main() {
                     for (name, _) in WIDGET_THEMES {
                         theme_choice.add_choice(name);
                     }
-                    theme_choice.set_value(current_theme_idx as i32);
+                    // Show the theme in use, which differs from the saved one in dark mode
+                    let shown_theme_idx = effective_theme_idx(&prefs);
+                    theme_choice.set_value(shown_theme_idx as i32);
 
                     // Widget Scheme
                     pack.add(
@@ -732,7 +2318,7 @@ fn main() {
                     for (name, _) in WIDGET_SCHEMES {
                         scheme_choice.add_choice(name);
                     }
-                    scheme_choice.set_value(current_scheme_idx as i32);
+                    scheme_choice.set_value(prefs.scheme_idx as i32);
 
                     // Color Theme
                     pack.add(
@@ -744,23 +2330,151 @@ fn main() {
                     for (name, _) in COLOR_THEMES {
                         color_choice.add_choice(name);
                     }
-                    color_choice.set_value(current_color_idx as i32);
+                    color_choice.set_value(prefs.color_idx as i32);
+
+                    let check_auto_theme = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Dark theme when the system is in dark mode");
+                    check_auto_theme.clone().set_checked(prefs.auto_theme);
 
                     let check_path = fltk::button::CheckButton::default()
                         .with_size(0, 30)
                         .with_label("Show full paths");
-                    check_path.clone().set_checked(current_show_full_path);
+                    check_path.clone().set_checked(prefs.show_full_path);
+
+                    let check_hash = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Show hashes instead of revision numbers");
+                    check_hash.clone().set_checked(prefs.show_hash);
+
+                    let check_color_rows = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Color rows by state");
+                    check_color_rows.clone().set_checked(prefs.color_rows);
+
+                    let check_row_icons = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Show state and phase icons");
+                    check_row_icons.clone().set_checked(prefs.row_icons);
+
+                    let check_group = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Group by parent folder");
+                    check_group.clone().set_checked(prefs.group_by_parent);
+
+                    let check_confirm_remove = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Confirm before removing repositories");
+                    check_confirm_remove.clone().set_checked(prefs.confirm_remove);
+
+                    let check_subrepos = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Recurse into subrepositories");
+                    check_subrepos.clone().set_checked(prefs.recurse_subrepos);
+
+                    let check_heads = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Flag branches with several heads");
+                    check_heads.clone().set_checked(prefs.check_heads);
+
+                    let check_batch_summary = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Show a summary of failed batches");
+                    check_batch_summary.clone().set_checked(prefs.show_batch_summary);
+
+                    let check_log = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Log hg commands to manahg.log");
+                    check_log.clone().set_checked(prefs.log_to_file);
 
-                    // Buttons in a Pack to ensure visibility
-                    let mut btn_pack = Pack::new(0, 0, 280, 40, "");
-                    btn_pack.set_type(fltk::group::PackType::Horizontal);
-                    btn_pack.set_spacing(20);
+                    let mut check_tray = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Minimize to tray on close");
+                    check_tray.set_checked(prefs.minimize_to_tray);
+                    if !cfg!(target_os = "windows") {
+                        check_tray.deactivate();
+                    }
+
+                    let parallel_row = Flex::default().with_size(0, 30).row();
+                    Frame::default().with_label("Parallel hg processes:");
+                    let mut parallel_spinner = fltk::misc::Spinner::default();
+                    parallel_spinner.set_range(1.0, 64.0);
+                    parallel_spinner.set_step(1.0);
+                    parallel_spinner.set_value(prefs.max_parallelism as f64);
+                    parallel_row.end();
+
+                    let mut vscode_row = Flex::default().with_size(0, 30).row();
+                    let vscode_label = Frame::default().with_label("VS Code command:");
+                    let mut vscode_input = fltk::input::Input::default();
+                    vscode_input.set_value(prefs.vscode_command.as_deref().unwrap_or("code"));
+                    vscode_row.fixed(&vscode_label, 130);
+                    vscode_row.end();
+
+                    // Column visibility, three per row; Path cannot be hidden
+                    pack.add(
+                        &Frame::default()
+                            .with_size(0, 20)
+                            .with_label("Columns:"),
+                    );
+                    let mut column_checks = Vec::new();
+                    let optional: Vec<usize> =
+                        (0..COLUMNS.len()).filter(|&i| COLUMNS[i].id != ColumnId::Path).collect();
+                    for chunk in optional.chunks(3) {
+                        let row = Flex::default().with_size(0, 25).row();
+                        for &i in chunk {
+                            let check = fltk::button::CheckButton::default().with_label(COLUMNS[i].name);
+                            check.clone().set_checked(column_visible(&prefs, i));
+                            column_checks.push((i, check));
+                        }
+                        row.end();
+                    }
 
-                    let mut btn_ok = Button::new(0, 0, 120, 30, "Apply");
-                    let mut btn_close = Button::new(0, 0, 120, 30, "Close");
-                    btn_pack.end();
+                    // Default scope of batch operations
+                    pack.add(
+                        &Frame::default()
+                            .with_size(0, 20)
+                            .with_label("Batch operations apply to:"),
+                    );
+                    let mut scope_choice = fltk::menu::Choice::default().with_size(0, 30);
+                    scope_choice.add_choice("Selected repositories");
+                    scope_choice.add_choice("All repositories in view");
+                    scope_choice.set_value(match prefs.default_scope {
+                        Scope::Selected => 0,
+                        Scope::View => 1,
+                    });
 
                     pack.end();
+                    general_scroll.end();
+                    let mut btn_ok = Button::new(10, 555, 120, 30, "Apply");
+                    let mut btn_close = Button::new(150, 555, 120, 30, "Close");
+                    general_tab.end();
+
+                    // One row per menu action; an empty field is no shortcut
+                    let shortcuts_tab = Group::new(5, 30, 290, 565, "Shortcuts");
+                    let custom_shortcuts = load_config().shortcuts;
+                    let scroll = fltk::group::Scroll::new(10, 40, 280, 505, "");
+                    let mut shortcut_pack = Pack::new(10, 40, 260, 0, "");
+                    shortcut_pack.set_spacing(4);
+                    let mut shortcut_inputs = Vec::new();
+                    for action in menu_actions(&prefs) {
+                        let name = action_name(action.path);
+                        let mut row = Flex::default().with_size(0, 26).row();
+                        let mut label = Frame::default()
+                            .with_label(name.rsplit('/').next().unwrap_or(&name))
+                            .with_align(fltk::enums::Align::Left | fltk::enums::Align::Inside);
+                        label.set_tooltip(&name);
+                        let mut input = fltk::input::Input::default();
+                        input.set_value(custom_shortcuts.get(&name).map(String::as_str).unwrap_or(action.shortcut));
+                        row.end();
+                        row.fixed(&input, 100);
+                        shortcut_inputs.push((name, action.shortcut, input));
+                    }
+                    shortcut_pack.end();
+                    scroll.end();
+                    let mut btn_shortcuts = Button::new(10, 555, 120, 30, "Apply");
+                    let mut btn_shortcuts_close = Button::new(150, 555, 120, 30, "Close");
+                    shortcuts_tab.end();
+                    tabs.end();
                     prefs_win.end();
                     prefs_win.make_modal(true);
                     prefs_win.show();
@@ -770,80 +2484,407 @@ fn main() {
                     let scheme_c = scheme_choice.clone();
                     let color_c = color_choice.clone();
                     let check_path_c = check_path.clone();
+                    let check_hash_c = check_hash.clone();
+                    let check_auto_theme_c = check_auto_theme.clone();
+                    let check_color_rows_c = check_color_rows.clone();
+                    let check_row_icons_c = check_row_icons.clone();
+                    let scope_c = scope_choice.clone();
+                    let check_group_c = check_group.clone();
+                    let check_confirm_remove_c = check_confirm_remove.clone();
+                    let check_subrepos_c = check_subrepos.clone();
+                    let parallel_c = parallel_spinner.clone();
+                    let check_log_c = check_log.clone();
+                    let check_tray_c = check_tray.clone();
+                    let check_heads_c = check_heads.clone();
+                    let check_batch_summary_c = check_batch_summary.clone();
+                    let vscode_c = vscode_input.clone();
+                    let base = prefs.clone();
 
                     btn_ok.set_callback(move |_| {
-                        sender.send(Message::UpdatePreferences(
-                            theme_c.value() as usize,
-                            scheme_c.value() as usize,
-                            color_c.value() as usize,
-                            check_path_c.is_checked(),
-                        ));
+                        // Picking a theme other than the one shown stops following the system
+                        let picked_theme_idx = theme_c.value() as usize;
+                        let (theme_idx, auto_theme) = if picked_theme_idx == shown_theme_idx {
+                            (base.theme_idx, check_auto_theme_c.is_checked())
+                        } else {
+                            (picked_theme_idx, false)
+                        };
+                        sender.send(Message::UpdatePreferences(Preferences {
+                            theme_idx,
+                            auto_theme,
+                            scheme_idx: scheme_c.value() as usize,
+                            color_idx: color_c.value() as usize,
+                            show_full_path: check_path_c.is_checked(),
+                            show_hash: check_hash_c.is_checked(),
+                            color_rows: check_color_rows_c.is_checked(),
+                            row_icons: check_row_icons_c.is_checked(),
+                            default_scope: if scope_c.value() == 1 {
+                                Scope::View
+                            } else {
+                                Scope::Selected
+                            },
+                            visible_columns: (0..COLUMNS.len())
+                                .map(|i| {
+                                    column_checks
+                                        .iter()
+                                        .find(|(idx, _)| *idx == i)
+                                        .is_none_or(|(_, c)| c.is_checked())
+                                })
+                                .collect(),
+                            group_by_parent: check_group_c.is_checked(),
+                            confirm_remove: check_confirm_remove_c.is_checked(),
+                            recurse_subrepos: check_subrepos_c.is_checked(),
+                            check_heads: check_heads_c.is_checked(),
+                            show_batch_summary: check_batch_summary_c.is_checked(),
+                            log_to_file: check_log_c.is_checked(),
+                            minimize_to_tray: check_tray_c.is_checked(),
+                            max_parallelism: parallel_c.value() as usize,
+                            // Left unset for the default `code`
+                            vscode_command: Some(vscode_c.value().trim().to_string())
+                                .filter(|c| !c.is_empty() && c != "code"),
+                            ..base.clone()
+                        }));
                     });
 
                     let mut pw_c = prefs_win.clone();
                     btn_close.set_callback(move |_| pw_c.hide());
+                    let mut pw_c = prefs_win.clone();
+                    btn_shortcuts_close.set_callback(move |_| pw_c.hide());
+
+                    let sender = s.clone();
+                    btn_shortcuts.set_callback(move |_| {
+                        let mut changed = HashMap::new();
+                        let mut used: Vec<(Shortcut, &str)> = Vec::new();
+                        for (name, default, input) in &shortcut_inputs {
+                            let text = input.value().trim().to_string();
+                            let Some(shortcut) = parse_shortcut(&text) else {
+                                dialog::alert(200, 200, &format!("Unknown shortcut '{}' for {}", text, name));
+                                return;
+                            };
+                            if shortcut != Shortcut::None {
+                                if let Some((_, other)) = used.iter().find(|(u, _)| *u == shortcut) {
+                                    dialog::alert(200, 200, &format!("{} is used by both {} and {}", text, other, name));
+                                    return;
+                                }
+                                used.push((shortcut, name));
+                            }
+                            if parse_shortcut(default) != Some(shortcut) {
+                                changed.insert(name.clone(), text);
+                            }
+                        }
+                        sender.send(Message::UpdateShortcuts(changed));
+                    });
+                }
+                Message::UpdateShortcuts(shortcuts) => {
+                    save_shortcuts(&shortcuts);
+                    for action in menu_actions(&prefs) {
+                        if let Some(mut item) = menu.find_item(action.path) {
+                            item.set_shortcut(action_shortcut(&action, &shortcuts, &mut Vec::new()));
+                        }
+                    }
+                    status_bar.set_label("Shortcuts saved");
                 }
-                Message::UpdatePreferences(t_idx, s_idx, c_idx, show_full) => {
-                    let mut config_changed = false;
+                Message::UpdatePreferences(mut new_prefs) => {
+                    // Keep the current indices if the new ones are out of range
+                    if new_prefs.theme_idx >= WIDGET_THEMES.len() {
+                        new_prefs.theme_idx = prefs.theme_idx;
+                    }
+                    if new_prefs.scheme_idx >= WIDGET_SCHEMES.len() {
+                        new_prefs.scheme_idx = prefs.scheme_idx;
+                    }
+                    if new_prefs.color_idx >= COLOR_THEMES.len() {
+                        new_prefs.color_idx = prefs.color_idx;
+                    }
 
-                    // Update indices if valid
-                    if t_idx < WIDGET_THEMES.len() && t_idx != current_theme_idx {
-                        current_theme_idx = t_idx;
-                        config_changed = true;
+                    if new_prefs == prefs {
+                        continue;
                     }
-                    if s_idx < WIDGET_SCHEMES.len() && s_idx != current_scheme_idx {
-                        current_scheme_idx = s_idx;
-                        config_changed = true;
+
+                    // Grouping changes the line layout, so capture the selection with the old preferences
+                    let selection = selected_paths(&browser, &app_state.lock().unwrap(), &prefs);
+                    let theme_changed = (effective_theme_idx(&new_prefs), new_prefs.scheme_idx, new_prefs.color_idx)
+                        != (effective_theme_idx(&prefs), prefs.scheme_idx, prefs.color_idx);
+                    if new_prefs.max_parallelism != prefs.max_parallelism {
+                        configure_worker_pool(new_prefs.max_parallelism);
                     }
-                    if c_idx < COLOR_THEMES.len() && c_idx != current_color_idx {
-                        current_color_idx = c_idx;
-                        config_changed = true;
+                    repo::set_log_file(new_prefs.log_to_file.then(log_file));
+                    prefs = new_prefs;
+
+                    // Keep the View menu toggle in step with the dialog
+                    if let Some(mut item) = menu.find_item("&View/Group by Parent Folder\t") {
+                        if prefs.group_by_parent {
+                            item.set();
+                        } else {
+                            item.clear();
+                        }
                     }
 
-                    if config_changed {
+                    if theme_changed {
                         // Re-apply all in order
-                        if current_theme_idx < WIDGET_THEMES.len() {
-                            if let Some(t) = WIDGET_THEMES[current_theme_idx].1 {
-                                WidgetTheme::new(t).apply();
-                            }
-                        }
-                        if current_scheme_idx < WIDGET_SCHEMES.len() {
-                            if let Some(s) = WIDGET_SCHEMES[current_scheme_idx].1 {
-                                WidgetScheme::new(s).apply();
-                            }
-                        }
-                        if current_color_idx < COLOR_THEMES.len() {
-                            if let Some(c) = COLOR_THEMES[current_color_idx].1 {
-                                ColorTheme::new(c).apply();
-                            }
-                        }
+                        apply_theme(&prefs);
                         app::redraw();
                     }
 
-                    if show_full != current_show_full_path {
-                        current_show_full_path = show_full;
-                        config_changed = true;
+                    // Rebuild an open legend so it picks up the new palette and columns
+                    if let Some(mut old) = legend_win.take()
+                        && old.shown()
+                    {
+                        old.hide();
+                        legend_win = Some(show_legend(&wind, &prefs));
                     }
 
-                    if config_changed {
-                        let repos = app_state.lock().unwrap();
-                        save_config(&repos, current_theme_idx, current_scheme_idx, current_color_idx, current_show_full_path);
-                        update_browser(&mut browser, &repos, current_show_full_path);
+                    let mut repos = app_state.lock().unwrap();
+                    {
+                        // Sorting by a column that is no longer shown would look random
+                        let mut state = sort_state.lock().unwrap();
+                        let key_count = state.keys.len();
+                        state.keys.retain(|&(c, _)| column_visible(&prefs, c));
+                        if state.keys.len() != key_count {
+                            save_sort(&state);
+                        }
+                        // Rev sorts differently with show_hash
+                        sort_repos(&mut repos, &state, &prefs);
+                        build_header(&mut header_group, &prefs, &state, s.clone());
                     }
+                    for r in repos.iter_mut() {
+                        apply_repo_prefs(r, &prefs);
+                    }
+                    save_config(&repos, &prefs);
+                    update_browser(&mut browser, &repos, &prefs, &selection);
                 }
                 Message::SelectAll => {
-                    // Start from 1 because line 0 is header? No, browser uses 1-based indexing for items.
-                    // Wait, Browser::size() returns item count.
                     // Multi-select browser requires select(line) to be called for each line.
-                    let count = browser.size();
-                    if count > 0 {
-                        for i in 1..=count {
-                            browser.select(i);
+                    // Group header rows are skipped.
+                    let rows = layout_rows(&app_state.lock().unwrap(), &prefs);
+                    for (i, row) in rows.iter().enumerate() {
+                        if let Row::Repo(_) = row {
+                            browser.select(i as i32 + 1);
+                        }
+                    }
+                }
+                Message::TypeAhead(typed) => {
+                    let repos = app_state.lock().unwrap();
+                    let rows = layout_rows(&repos, &prefs);
+                    let typed = typed.to_lowercase();
+                    // Pressing the same letter again moves on to the next match,
+                    // a longer text keeps the current row while it still matches
+                    let current = browser.selected_items().first().map_or(0, |&line| line as usize - 1);
+                    let start = if typed.chars().count() == 1 { current + 1 } else { current };
+                    let matched = (0..rows.len()).map(|i| (start + i) % rows.len()).find(|&i| match rows[i] {
+                        Row::Repo(idx) => cell_text(ColumnId::Path, &repos[idx], &prefs)
+                            .to_lowercase()
+                            .starts_with(&typed),
+                        Row::Group(_) => false,
+                    });
+                    if let Some(i) = matched {
+                        let line = i as i32 + 1;
+                        for selected in browser.selected_items() {
+                            browser.deselect(selected);
+                        }
+                        browser.select(line);
+                        browser.middle_line(line);
+                        s.send(Message::SelectionChanged);
+                    }
+                }
+                Message::InvertSelection => {
+                    // Only the rows shown are toggled, group header rows stay unselected
+                    let rows = layout_rows(&app_state.lock().unwrap(), &prefs);
+                    let mut count = 0;
+                    for (i, row) in rows.iter().enumerate() {
+                        let line = i as i32 + 1;
+                        if let Row::Repo(_) = row {
+                            if browser.selected(line) {
+                                browser.deselect(line);
+                            } else {
+                                browser.select(line);
+                                count += 1;
+                            }
+                        }
+                    }
+                    status_bar.set_label(&format!("{} repositories selected", count));
+                    s.send(Message::SelectionChanged);
+                }
+                Message::SelectNone => {
+                    for line in browser.selected_items() {
+                        browser.deselect(line);
+                    }
+                    status_bar.set_label("0 repositories selected");
+                    s.send(Message::SelectionChanged);
+                }
+                Message::SelectModified | Message::SelectErrored => {
+                    let errored = matches!(msg, Message::SelectErrored);
+                    let repos = app_state.lock().unwrap();
+                    let paths: HashSet<PathBuf> = repos
+                        .iter()
+                        .filter(|r| if errored { r.last_status.starts_with("Error") || r.missing } else { r.modified })
+                        .map(|r| r.path.clone())
+                        .collect();
+                    // Repositories hidden by the view are not selected
+                    let lines = lines_for_paths(&repos, &prefs, &paths);
+                    if lines.is_empty() {
+                        status_bar.set_label(if errored {
+                            "No failed or missing repository in the list"
+                        } else {
+                            "No modified repository in the list"
+                        });
+                        continue;
+                    }
+                    for line in browser.selected_items() {
+                        browser.deselect(line);
+                    }
+                    for &line in &lines {
+                        browser.select(line);
+                    }
+                    status_bar.set_label(&format!("{} repositories selected", lines.len()));
+                    s.send(Message::SelectionChanged);
+                }
+                Message::SelectPaths(paths) => {
+                    let repos = app_state.lock().unwrap();
+                    let lines = lines_for_paths(&repos, &prefs, &paths.into_iter().collect());
+                    if lines.is_empty() {
+                        status_bar.set_label("None of these repositories is in the list");
+                        continue;
+                    }
+                    for line in browser.selected_items() {
+                        browser.deselect(line);
+                    }
+                    for &line in &lines {
+                        browser.select(line);
+                    }
+                    status_bar.set_label(&format!("{} repositories selected", lines.len()));
+                    s.send(Message::SelectionChanged);
+                }
+                Message::OpenSelectMatching => {
+                    let mut win = Window::default().with_size(400, 130).with_label("Select Matching");
+                    win.set_border(true);
+                    let mut flex = Flex::new(10, 10, 380, 110, "").column();
+                    flex.set_spacing(5);
+
+                    let label = Frame::default().with_label("Path or branch contains, or matches (* and ?):");
+                    flex.fixed(&label, 20);
+                    let mut pattern_input = fltk::input::Input::default();
+                    pattern_input.set_value(&last_match.0);
+                    flex.fixed(&pattern_input, 25);
+                    let check_case = fltk::button::CheckButton::default().with_label("Case sensitive");
+                    check_case.clone().set_checked(last_match.1);
+                    flex.fixed(&check_case, 20);
+
+                    let btn_row = Flex::default().row();
+                    let mut btn_cancel = Button::default().with_label("Cancel");
+                    let mut btn_ok = Button::default().with_label("Select");
+                    btn_row.end();
+                    flex.fixed(&btn_row, 30);
+
+                    flex.end();
+                    win.end();
+                    win.make_modal(true);
+                    win.show();
+                    let _ = pattern_input.take_focus();
+
+                    let mut w_c = win.clone();
+                    btn_cancel.set_callback(move |_| w_c.hide());
+
+                    let sender = s.clone();
+                    let mut w_c2 = win.clone();
+                    btn_ok.set_callback(move |_| {
+                        let pattern = pattern_input.value().trim().to_string();
+                        if pattern.is_empty() {
+                            return;
+                        }
+                        sender.send(Message::SelectMatching(pattern, check_case.is_checked()));
+                        w_c2.hide();
+                    });
+                }
+                Message::SelectMatching(pattern, case_sensitive) => {
+                    let repos = app_state.lock().unwrap();
+                    let paths: HashSet<PathBuf> = repos
+                        .iter()
+                        .filter(|r| {
+                            matches_pattern(&pattern, &r.path.display().to_string(), case_sensitive)
+                                || r.current_branch.as_deref().is_some_and(|b| matches_pattern(&pattern, b, case_sensitive))
+                        })
+                        .map(|r| r.path.clone())
+                        .collect();
+                    let lines = lines_for_paths(&repos, &prefs, &paths);
+                    last_match = (pattern, case_sensitive);
+                    if lines.is_empty() {
+                        status_bar.set_label(&format!("No repository in the list matches '{}'", last_match.0));
+                        continue;
+                    }
+                    for line in browser.selected_items() {
+                        browser.deselect(line);
+                    }
+                    for &line in &lines {
+                        browser.select(line);
+                    }
+                    status_bar.set_label(&format!("{} repositories selected", lines.len()));
+                    s.send(Message::SelectionChanged);
+                }
+                Message::SelectionChanged => {
+                    // Group header rows are not selectable
+                    let repos = app_state.lock().unwrap();
+                    let rows = layout_rows(&repos, &prefs);
+                    for line in browser.selected_items() {
+                        if let Some(Row::Group(_)) = rows.get(line as usize - 1) {
+                            browser.deselect(line);
+                        }
+                    }
+
+                    let tooltip = repo_index_for_line(&rows, browser.value())
+                        .map(|idx| repo_tooltip(&repos[idx]))
+                        .unwrap_or_default();
+                    browser.set_tooltip(&tooltip);
+
+                    let sel = get_selected_repos(&browser, &repos, &prefs);
+                    update_summary_bar(&browser, &repos);
+
+                    if prefs.show_details_panel {
+                        details_path = None;
+                        match sel.as_slice() {
+                            [] => fill_status_panel(&mut details_panel, &[], ""),
+                            [repo] => {
+                                details_path = Some(repo.path.clone());
+                                fill_status_panel(&mut details_panel, &[], &format!("{}\n\nLoading...", repo.path.display()));
+                                load_repo_details(repo.clone(), s.clone());
+                            }
+                            _ => {
+                                let dirty = sel.iter().filter(|r| r.modified).count();
+                                let note = format!("{} repositories selected\n{} with uncommitted changes", sel.len(), dirty);
+                                fill_status_panel(&mut details_panel, &[], &note);
+                            }
+                        }
+                    }
+
+                    // The file status panel shows the first selected repo
+                    if prefs.show_status_panel {
+                        status_panel_path = sel.first().map(|r| r.path.clone());
+                        match sel.first() {
+                            Some(repo) => load_status_files(repo.clone(), s.clone()),
+                            None => fill_status_panel(&mut status_panel, &[], ""),
+                        }
+                    }
+
+                    // Single-repository actions are greyed out for multi-selection
+                    let single = sel.len() <= 1;
+                    for mut item in [
+                        menu.find_item("&Action/Show Diff...\t"),
+                        popup_menu.find_item("Show Diff...\t"),
+                        menu.find_item("&Action/Show Graph...\t"),
+                        popup_menu.find_item("Show Graph...\t"),
+                        menu.find_item("&Action/Edit Remote Paths...\t"),
+                        popup_menu.find_item("Edit Remote Paths...\t"),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    {
+                        if single {
+                            item.activate();
+                        } else {
+                            item.deactivate();
                         }
                     }
                 }
                 Message::Copy => {
-                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap());
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
                     if !sel.is_empty() {
                         let text: String = sel
                             .iter()
@@ -853,8 +2894,115 @@ fn main() {
                         app::copy(&text);
                     }
                 }
+                Message::CopyTable => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    if !sel.is_empty() {
+                        app::copy(&markdown_table(&sel, &prefs));
+                    }
+                }
+                Message::CopyHash => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    if sel.is_empty() {
+                        continue;
+                    }
+                    // Full hashes are not kept on refresh, so ask hg now
+                    let sender = s;
+                    spawn_worker(move || {
+                        let hashes: Vec<String> = sel
+                            .par_iter()
+                            .map(|r| r.get_full_node().unwrap_or_default())
+                            .collect();
+                        sender.send(Message::HashesLoaded(copy_text(&sel, &hashes)));
+                    });
+                }
+                Message::HashesLoaded(text) => app::copy(&text),
+                Message::CopyBranch => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    if sel.is_empty() {
+                        continue;
+                    }
+                    let branches: Vec<String> =
+                        sel.iter().map(|r| r.current_branch.clone().unwrap_or_default()).collect();
+                    app::copy(&copy_text(&sel, &branches));
+                }
+                Message::Rename => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    let Some(repo) = sel.first() else {
+                        status_bar.set_label("Select a repository to rename");
+                        continue;
+                    };
+                    let prompt = format!("Display name for {}\n(leave empty to use the folder name):", repo.path.display());
+                    let current = repo.alias.clone().unwrap_or_default();
+                    let Some(name) = dialog::input_default(&prompt, &current) else {
+                        continue;
+                    };
+                    let name = name.trim();
+
+                    let mut repos = app_state.lock().unwrap();
+                    let selection = selected_paths(&browser, &repos, &prefs);
+                    if let Some(r) = repos.iter_mut().find(|r| r.path == repo.path) {
+                        r.alias = if name.is_empty() { None } else { Some(name.to_string()) };
+                    }
+                    save_config(&repos, &prefs);
+                    update_browser(&mut browser, &repos, &prefs, &selection);
+                    if prefs.show_full_path {
+                        status_bar.set_label("Aliases are shown when full paths are turned off in Preferences");
+                    }
+                }
+                Message::Locate => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    let Some(repo) = sel.iter().find(|r| r.missing) else {
+                        status_bar.set_label("Select a missing repository to locate");
+                        continue;
+                    };
+                    let mut chooser =
+                        dialog::NativeFileChooser::new(dialog::NativeFileChooserType::BrowseDir);
+                    chooser.set_title(&format!("Locate {}", repo.path.display()));
+                    chooser.show();
+                    let new_path = chooser.filename();
+                    if new_path.as_os_str().is_empty() {
+                        continue;
+                    }
+                    if !new_path.join(".hg").is_dir() {
+                        dialog::alert(200, 200, &format!("{} is not a Mercurial repository.", new_path.display()));
+                        continue;
+                    }
+
+                    let mut repos = app_state.lock().unwrap();
+                    if repos.iter().any(|r| r.path == new_path) {
+                        dialog::alert(200, 200, &format!("{} is already in the list.", new_path.display()));
+                        continue;
+                    }
+                    let Some(r) = repos.iter_mut().find(|r| r.path == repo.path) else {
+                        continue;
+                    };
+                    r.path = new_path;
+                    r.last_status = "Refreshing...".to_string();
+                    let located = r.clone();
+                    save_config(&repos, &prefs);
+                    update_browser(&mut browser, &repos, &prefs, &HashSet::from([located.path.clone()]));
+                    spawn_batch(vec![located], s.clone(), |_| Ok("Located".to_string()));
+                }
+                Message::RowActivated => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    if sel.first().is_some_and(|r| r.last_status.starts_with("Error")) {
+                        s.send(Message::ShowErrorDetails);
+                    } else {
+                        s.send(Message::OpenTortoiseHg);
+                    }
+                }
+                Message::ShowErrorDetails => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    match sel.first() {
+                        Some(repo) if repo.last_error.is_some() || repo.last_status.starts_with("Error") => {
+                            show_error_details(repo);
+                        }
+                        Some(_) => status_bar.set_label("The last operation on this repository did not fail"),
+                        None => status_bar.set_label("Select a repository to show its error"),
+                    }
+                }
                 Message::OpenTortoiseHg => {
-                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap());
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
                     if let Some(repo) = sel.first() {
                         let path = &repo.path;
                         // Try to launch thg (TortoiseHg Workbench)
@@ -877,98 +3025,515 @@ fn main() {
                         }
                     }
                 }
-                Message::PullAll | Message::PullCurrent | Message::UpdateLatest => {
-                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap());
+                Message::OpenVsCode => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    let command = prefs.vscode_command.as_deref().unwrap_or("code");
+                    // One alert for all the repositories that failed
+                    let failures: Vec<String> = sel
+                        .iter()
+                        .filter_map(|r| open_in_vscode(command, &r.path).err().map(|e| format!("{}: {}", r.path.display(), e)))
+                        .collect();
+                    if !failures.is_empty() {
+                        dialog::alert(
+                            200,
+                            200,
+                            &format!("Failed to launch VS Code ({}):\n{}", command, failures.join("\n")),
+                        );
+                    }
+                }
+                Message::ShowOutputLog => {
+                    match output_log_view.as_mut() {
+                        Some((win, _)) if win.shown() => win.show(),
+                        _ => output_log_view = Some(show_output_log(&output_log)),
+                    }
+                }
+                Message::LogLine(text) => {
+                    output_log.append(&text);
+                    // Trimmed in steps so it does not happen on every command
+                    let lines = output_log.count_lines(0, output_log.length());
+                    if lines > MAX_OUTPUT_LOG_LINES + 500 {
+                        let mut cut = 0;
+                        for _ in 0..lines - MAX_OUTPUT_LOG_LINES {
+                            cut = output_log.line_end(cut) + 1;
+                        }
+                        output_log.remove(0, cut);
+                    }
+                    if let Some((win, display)) = output_log_view.as_mut()
+                        && win.shown()
+                    {
+                        display.set_insert_position(output_log.length());
+                        display.show_insert_position();
+                    }
+                }
+                Message::ViewLog => {
+                    if !log_file().is_file() {
+                        let hint = if prefs.log_to_file {
+                            "No hg command has been logged yet."
+                        } else {
+                            "Logging is off. Turn on \"Log hg commands to manahg.log\" in Preferences."
+                        };
+                        dialog::message(200, 200, hint);
+                        continue;
+                    }
+                    if let Err(e) = open_in_default_app(&log_file()) {
+                        dialog::alert(200, 200, &format!("Failed to open {}: {}", log_file().display(), e));
+                    }
+                }
+                Message::PullAll
+                | Message::PullCurrent
+                | Message::PullSubrepos
+                | Message::UpdateLatest
+                | Message::UpdateLastPublic => {
+                    let op = match msg {
+                        Message::PullAll => Operation::PullAll,
+                        Message::PullCurrent => Operation::PullCurrent,
+                        Message::PullSubrepos => Operation::PullSubrepos,
+                        Message::UpdateLatest => Operation::UpdateLatest,
+                        _ => Operation::UpdateLastPublic,
+                    };
+                    let op_name = op.to_string();
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) =
+                        confirm_targets(&op_name, &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
                     if sel.is_empty() {
                         status_bar.set_label("No repository selected");
                         continue;
                     }
+                    let Some(sel) = confirm_applied_patches(&op_name, sel) else {
+                        continue;
+                    };
 
                     status_bar.set_label("Processing...");
                     let sender = s.clone();
-                    let op = msg.clone();
 
                     for repo in &sel {
-                        // Create a unique task ID
-                        let _task_id = repo
-                            .path
-                            .file_name()
-                            .unwrap_or_default()
-                            .to_string_lossy()
-                            .to_string();
-                        let op_name = match op {
-                            Message::PullAll => "Pull All Branches",
-                            Message::PullCurrent => "Pull Current Branch",
-                            Message::UpdateLatest => "Update to Latest",
-                            _ => "Unknown",
-                        };
                         sender.send(Message::SetStatus(
                             repo.path.clone(),
                             format!("{}...", op_name),
                         ));
                     }
 
-                    thread::spawn(move || {
-                        sel.par_iter().for_each(|repo| {
-                            let _op_name = match op {
-                                Message::PullAll => "Pull All Branches",
-                                Message::PullCurrent => "Pull Current Branch",
-                                Message::UpdateLatest => "Update to Latest",
-                                _ => "Unknown",
-                            };
+                    spawn_operation(op, sel, sender);
+                }
+                Message::PullAndUpdate => {
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) = confirm_targets("Pull then Update", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
+                    if sel.is_empty() {
+                        status_bar.set_label("No repository selected");
+                        continue;
+                    }
+                    let Some(sel) = confirm_applied_patches("Pull then Update", sel) else {
+                        continue;
+                    };
 
-                            let mut updated_repo = repo.clone(); // Clone to update state
-                            let res = match op {
-                                Message::PullAll => updated_repo.pull_all_branches(),
-                                Message::PullCurrent => updated_repo.pull_current_branch(),
-                                Message::UpdateLatest => updated_repo.update_to_latest(),
-                                _ => Ok("".into()),
-                            };
+                    status_bar.set_label("Processing...");
+                    let sender = s.clone();
+
+                    // A single `hg pull -u` per repo, so there is no separate "Updating..." step to show
+                    for repo in &sel {
+                        sender.send(Message::SetStatus(
+                            repo.path.clone(),
+                            "Pulling and updating...".to_string(),
+                        ));
+                    }
+
+                    spawn_operation(Operation::PullAndUpdate, sel, sender);
+                }
+                Message::Sync => {
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) = confirm_targets("Sync", &browser, &repos, &prefs) else {
+                        continue;
+                    };
+                    if sel.is_empty() {
+                        status_bar.set_label("No repository selected");
+                        continue;
+                    }
+                    let Some(sel) = confirm_applied_patches("Sync", sel) else {
+                        continue;
+                    };
 
-                            // Refresh repo state after op (revision might change)
-                            updated_repo.refresh();
+                    status_bar.set_label("Syncing...");
+                    let sender = s.clone();
 
-                            match res {
-                                Ok(_) => {
-                                    updated_repo.last_status = "Success".to_string();
-                                    sender.send(Message::RepoUpdated(updated_repo));
-                                }
-                                Err(e) => {
-                                    updated_repo.last_status = format!("Error: {}", e);
-                                    sender.send(Message::RepoUpdated(updated_repo));
-                                }
+                    for repo in &sel {
+                        sender.send(Message::SetStatus(repo.path.clone(), "Syncing...".to_string()));
+                    }
+
+                    spawn_operation(Operation::Sync, sel, sender);
+                }
+                Message::CheckRemotes => {
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) = confirm_targets("Check Remotes", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
+                    if sel.is_empty() {
+                        status_bar.set_label("Select repositories to check");
+                        continue;
+                    }
+
+                    status_bar.set_label("Checking remotes...");
+                    let sender = s.clone();
+
+                    for r in &sel {
+                        sender.send(Message::SetStatus(
+                            r.path.clone(),
+                            "Checking remote...".to_string(),
+                        ));
+                    }
+
+                    spawn_operation(Operation::CheckRemotes, sel, sender);
+                }
+                Message::RetryFailed => {
+                    // Failed repos grouped by their operation, in list order
+                    let mut groups: Vec<(Operation, Vec<Repository>)> = Vec::new();
+                    for r in app_state.lock().unwrap().iter() {
+                        let Some(op) = r.last_operation else {
+                            continue;
+                        };
+                        if !r.last_status.starts_with("Error") {
+                            continue;
+                        }
+                        match groups.iter_mut().find(|(o, _)| *o == op) {
+                            Some((_, group)) => group.push(r.clone()),
+                            None => groups.push((op, vec![r.clone()])),
+                        }
+                    }
+                    if groups.is_empty() {
+                        status_bar.set_label("No failed operation to retry");
+                        continue;
+                    }
+
+                    // The same mq patch check as the original actions; only Check Remotes leaves the working directory alone
+                    let mut checked = Vec::new();
+                    for (op, targets) in groups {
+                        if op == Operation::CheckRemotes {
+                            checked.push((op, targets));
+                        } else if let Some(targets) = confirm_applied_patches(&op.to_string(), targets) {
+                            checked.push((op, targets));
+                        }
+                    }
+                    let groups = checked;
+                    if groups.is_empty() {
+                        status_bar.set_label("Retry cancelled");
+                        continue;
+                    }
+
+                    // The retried pulls and syncs are not the transactions Undo knows about
+                    undoable = None;
+
+                    let total: usize = groups.iter().map(|(_, g)| g.len()).sum();
+                    let counts: Vec<String> = groups.iter().map(|(op, g)| format!("{} {}", g.len(), op)).collect();
+                    status_bar.set_label(&format!("Retrying {} repositories: {}", total, counts.join(", ")));
+                    for (op, targets) in groups {
+                        for r in &targets {
+                            s.send(Message::SetStatus(r.path.clone(), format!("{}...", op)));
+                        }
+                        spawn_operation(op, targets, s.clone());
+                    }
+                }
+                Message::Push => {
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) = confirm_targets("Push", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
+                    if sel.is_empty() {
+                        status_bar.set_label("Select repositories to push");
+                        continue;
+                    }
+
+                    status_bar.set_label("Checking outgoing changesets...");
+                    let sender = s.clone();
+                    spawn_worker(move || {
+                        let summaries = sel
+                            .par_iter()
+                            .map(|r| OutgoingSummary {
+                                path: r.path.clone(),
+                                changesets: r.get_outgoing_log(OUTGOING_LIMIT).map_err(|e| e.to_string()),
+                                secret: r.count_secret().unwrap_or(0),
+                            })
+                            .collect();
+                        sender.send(Message::ConfirmPush(summaries));
+                    });
+                }
+                Message::ConfirmPush(summaries) => {
+                    status_bar.set_label("Ready");
+                    show_push_confirmation(&summaries, s.clone());
+                }
+                Message::DoPush(paths, skipped) => {
+                    let targets = repos_by_path(&app_state.lock().unwrap(), &paths);
+                    if targets.is_empty() {
+                        status_bar.set_label(&format!("Push: nothing pushed, {} skipped", skipped));
+                        continue;
+                    }
+
+                    status_bar.set_label("Pushing...");
+                    let sender = s;
+
+                    for r in &targets {
+                        sender.send(Message::SetStatus(
+                            r.path.clone(),
+                            "Pushing...".to_string(),
+                        ));
+                    }
+
+                    if skipped > 0 {
+                        sender.send(Message::LogLine(format!("Push: {} unchecked repositories skipped\n", skipped)));
+                    }
+                    spawn_operation(Operation::Push, targets, sender);
+                }
+                Message::OpenForceUpdate => {
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) =
+                        confirm_targets("Force Update", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
+                    if sel.is_empty() {
+                        status_bar.set_label("Select repositories to update");
+                        continue;
+                    }
+                    let Some(sel) = confirm_applied_patches("Force Update", sel) else {
+                        continue;
+                    };
+
+                    status_bar.set_label("Collecting changes...");
+                    let sender = s;
+
+                    // Count what each dirty repo would lose off the UI thread; clean repos just update
+                    spawn_worker(move || {
+                        let dirty: Vec<(PathBuf, usize)> = sel
+                            .par_iter()
+                            .filter(|r| r.modified)
+                            .filter_map(|r| match r.get_changed_files() {
+                                Ok(files) if !files.is_empty() => Some((r.path.clone(), files.len())),
+                                _ => None,
+                            })
+                            .collect();
+                        let paths = sel.into_iter().map(|r| r.path).collect();
+                        sender.send(Message::ForceUpdatePreviewed(paths, dirty));
+                    });
+                }
+                Message::ForceUpdatePreviewed(paths, dirty) => {
+                    status_bar.set_label("Ready");
+
+                    let mut preview = String::new();
+                    if dirty.is_empty() {
+                        preview.push_str("None of the repositories has local changes.\n");
+                    } else {
+                        for (path, count) in &dirty {
+                            preview.push_str(&format!("{}\n    {} files will be discarded\n", path.display(), count));
+                        }
+                    }
+                    let clean = paths.len() - dirty.len();
+                    if clean > 0 {
+                        preview.push_str(&format!("\n{} clean repositories will be updated normally.\n", clean));
+                    }
+
+                    let mut dialog = Window::default()
+                        .with_size(600, 400)
+                        .with_label("Force Update");
+                    dialog.set_border(true);
+                    let mut flex = Flex::new(10, 10, 580, 380, "").column();
+                    flex.set_spacing(10);
+
+                    let header = Frame::default().with_label(&format!(
+                        "Local changes will be discarded in {} of {} repositories:",
+                        dirty.len(),
+                        paths.len()
+                    ));
+                    flex.fixed(&header, 20);
+
+                    let mut buf = fltk::text::TextBuffer::default();
+                    buf.set_text(&preview);
+                    let mut display = fltk::text::TextDisplay::default();
+                    display.set_buffer(buf);
+
+                    let btn_row = Flex::default().row();
+                    let mut btn_cancel = Button::default().with_label("Cancel");
+                    let mut btn_ok = Button::default().with_label("Update");
+                    btn_row.end();
+                    flex.fixed(&btn_row, 30);
+
+                    flex.end();
+                    dialog.end();
+                    dialog.make_modal(true);
+                    dialog.show();
+
+                    let mut d_clone = dialog.clone();
+                    btn_cancel.set_callback(move |_| d_clone.hide());
+
+                    let s_clone = s;
+                    let mut d_clone2 = dialog.clone();
+                    btn_ok.set_callback(move |_| {
+                        s_clone.send(Message::DoForceUpdate(paths.clone()));
+                        d_clone2.hide();
+                    });
+                }
+                Message::DoForceUpdate(paths) => {
+                    let targets = repos_by_path(&app_state.lock().unwrap(), &paths);
+                    if targets.is_empty() {
+                        continue;
+                    }
+
+                    status_bar.set_label("Updating...");
+                    let sender = s.clone();
+
+                    for r in &targets {
+                        sender.send(Message::SetStatus(
+                            r.path.clone(),
+                            "Updating...".to_string(),
+                        ));
+                    }
+
+                    spawn_recorded_batch(targets, sender, None, |r| {
+                        if r.modified {
+                            r.update_clean(None)?;
+                            Ok(RepoOutcome { status: "Updated (changes discarded)".to_string(), transaction: Transaction::Cleared })
+                        } else {
+                            r.update_to_latest().map(|_| "Updated".to_string().into())
+                        }
+                    });
+                }
+                Message::OpenUpdateRev => {
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) = confirm_targets("Update to Revision", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
+                    if sel.is_empty() {
+                        status_bar.set_label("Select repositories to update");
+                        continue;
+                    }
+                    let Some(sel) = confirm_applied_patches("Update to Revision", sel) else {
+                        continue;
+                    };
+                    let dirty = sel.iter().filter(|r| r.modified).count();
+
+                    let mut dialog = Window::default()
+                        .with_size(400, 170)
+                        .with_label("Update to Revision");
+                    dialog.set_border(true);
+                    let mut flex = Flex::new(10, 10, 380, 150, "").column();
+                    flex.set_spacing(5);
+
+                    let label = Frame::default().with_label("Revision, hash, tag or bookmark:");
+                    flex.fixed(&label, 20);
+                    let rev_input = fltk::input::Input::default();
+                    flex.fixed(&rev_input, 25);
+
+                    let mut warning = Frame::default();
+                    if dirty > 0 {
+                        warning.set_label(&format!("{} of {} repositories have uncommitted changes.", dirty, sel.len()));
+                        warning.set_label_color(Color::Red);
+                    }
+                    flex.fixed(&warning, 20);
+                    let check_clean = fltk::button::CheckButton::default()
+                        .with_label("Discard uncommitted changes (--clean)");
+                    flex.fixed(&check_clean, 25);
+
+                    let btn_row = Flex::default().row();
+                    let mut btn_cancel = Button::default().with_label("Cancel");
+                    let mut btn_ok = Button::default().with_label("Update");
+                    btn_row.end();
+                    flex.fixed(&btn_row, 30);
+
+                    flex.end();
+                    dialog.end();
+                    dialog.make_modal(true);
+                    dialog.show();
+
+                    let mut d_clone = dialog.clone();
+                    btn_cancel.set_callback(move |_| d_clone.hide());
+
+                    let s_clone = s.clone();
+                    let mut d_clone2 = dialog.clone();
+                    let paths: Vec<PathBuf> = sel.iter().map(|r| r.path.clone()).collect();
+                    btn_ok.set_callback(move |_| {
+                        let rev = rev_input.value().trim().to_string();
+                        if rev.is_empty() {
+                            return;
+                        }
+                        let clean = check_clean.is_checked();
+                        if clean && dirty > 0 {
+                            let question = format!(
+                                "Uncommitted changes in {} repositories will be lost. Continue?",
+                                dirty
+                            );
+                            if dialog::choice2(200, 200, &question, "Cancel", "Discard and Update", "") != Some(1) {
+                                return;
                             }
-                        });
-                        sender.send(Message::SetGlobalStatus("Ready".into()));
+                        }
+                        s_clone.send(Message::DoUpdateRev(paths.clone(), rev, clean));
+                        d_clone2.hide();
+                    });
+                }
+                Message::DoUpdateRev(paths, rev, clean) => {
+                    let targets = repos_by_path(&app_state.lock().unwrap(), &paths);
+                    if targets.is_empty() {
+                        continue;
+                    }
+
+                    status_bar.set_label("Updating...");
+                    let sender = s.clone();
+
+                    for r in &targets {
+                        sender.send(Message::SetStatus(r.path.clone(), format!("Updating to {}...", rev)));
+                    }
+
+                    spawn_recorded_batch(targets, sender, None, move |r| {
+                        let res = if clean { r.update_clean(Some(&rev)) } else { r.update_to_rev(&rev) };
+                        let transaction = if clean { Transaction::Cleared } else { Transaction::Unchanged };
+                        res.map(|_| RepoOutcome { status: format!("Updated to {}", rev), transaction })
                     });
                 }
                 Message::OpenSwitchBranch => {
-                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap());
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) =
+                        confirm_targets("Switch Branch", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
                     if sel.is_empty() {
                         status_bar.set_label("Select repositories to switch branch");
                         continue;
                     }
+                    let Some(sel) = confirm_applied_patches("Switch Branch", sel) else {
+                        continue;
+                    };
 
                     status_bar.set_label("Analyzing branches...");
+                    let sender = s.clone();
 
-                    // Retrieve all branches with counts
-                    use std::collections::HashMap;
-
-                    let mut branch_counts: HashMap<String, usize> = HashMap::new();
-                    let total_sel = sel.len();
+                    // One `hg branches` per repo, run in parallel off the UI thread
+                    spawn_worker(move || {
+                        let found: Vec<(PathBuf, Vec<String>)> = sel
+                            .par_iter()
+                            .map(|r| (r.path.clone(), r.get_all_branches().unwrap_or_default()))
+                            .collect();
 
-                    for r in &sel {
-                        if let Ok(branches) = r.get_all_branches() {
-                            for b in branches {
-                                *branch_counts.entry(b).or_insert(0) += 1;
-                            }
+                        let mut branch_counts: HashMap<String, usize> = HashMap::new();
+                        for b in found.iter().flat_map(|(_, branches)| branches) {
+                            *branch_counts.entry(b.clone()).or_insert(0) += 1;
                         }
-                    }
+                        let mut sorted_branches: Vec<(String, usize)> = branch_counts.into_iter().collect();
+                        // Sort by count (descending) then name (ascending)
+                        sorted_branches.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
 
-                    let mut sorted_branches: Vec<(String, usize)> =
-                        branch_counts.into_iter().collect();
-                    // Sort by count (descending) then name (ascending)
-                    sorted_branches.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                        let paths = found.into_iter().map(|(path, _)| path).collect();
+                        sender.send(Message::BranchesAnalyzed(paths, sorted_branches));
+                    });
+                }
+                Message::BranchesAnalyzed(target_paths, sorted_branches) => {
+                    status_bar.set_label("Ready");
+                    let total_sel = target_paths.len();
 
                     let branch_names: Vec<String> =
                         sorted_branches.iter().map(|(n, _)| n.clone()).collect();
@@ -1031,13 +3596,13 @@ fn main() {
                         };
 
                         if !target.is_empty() {
-                            s_clone.send(Message::SwitchBranch(target));
+                            s_clone.send(Message::SwitchBranch(target_paths.clone(), target));
                             d_clone2.hide();
                         }
                     });
                 }
-                Message::SwitchBranch(target_branch) => {
-                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap());
+                Message::SwitchBranch(paths, target_branch) => {
+                    let sel = repos_by_path(&app_state.lock().unwrap(), &paths);
                     if sel.is_empty() {
                         continue;
                     }
@@ -1052,51 +3617,48 @@ fn main() {
                         ));
                     }
 
-                    thread::spawn(move || {
-                        sel.par_iter().for_each(|repo| {
-                            let mut r = repo.clone();
-                            let res = r.update_branch(&target_branch);
-                            r.refresh();
-                            match res {
-                                Ok(_) => {
-                                    r.last_status = "Switched".to_string();
-                                    sender.send(Message::RepoUpdated(r));
-                                }
-                                Err(e) => {
-                                    r.last_status = format!("Error: {}", e);
-                                    sender.send(Message::RepoUpdated(r));
-                                }
-                            }
-                        });
-                        sender.send(Message::SetGlobalStatus("Ready".into()));
-                    });
+                    spawn_batch(sel, sender, move |r| r.update_branch(&target_branch).map(|_| "Switched".to_string()));
                 }
                 Message::OpenUpdateTag => {
-                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap());
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) =
+                        confirm_targets("Update to Tag", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
                     if sel.is_empty() {
                         status_bar.set_label("Select repositories to update to tag");
                         continue;
                     }
+                    let Some(sel) = confirm_applied_patches("Update to Tag", sel) else {
+                        continue;
+                    };
 
                     status_bar.set_label("Analyzing tags...");
-
-                    use std::collections::HashMap;
-
-                    let mut tag_counts: HashMap<String, usize> = HashMap::new();
-                    let total_sel = sel.len();
-
-                    for r in &sel {
-                        if let Ok(tags) = r.get_all_tags() {
-                            for t in tags {
-                                *tag_counts.entry(t).or_insert(0) += 1;
-                            }
+                    let sender = s;
+
+                    // One `hg tags` per repo, run in parallel off the UI thread
+                    spawn_worker(move || {
+                        let found: Vec<(PathBuf, Vec<String>)> = sel
+                            .par_iter()
+                            .map(|r| (r.path.clone(), r.get_tags().unwrap_or_default()))
+                            .collect();
+
+                        let mut tag_counts: HashMap<String, usize> = HashMap::new();
+                        for t in found.iter().flat_map(|(_, tags)| tags) {
+                            *tag_counts.entry(t.clone()).or_insert(0) += 1;
                         }
-                    }
+                        let mut sorted_tags: Vec<(String, usize)> = tag_counts.into_iter().collect();
+                        // Sort by count (descending) then name (ascending)
+                        sorted_tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
 
-                    let mut sorted_tags: Vec<(String, usize)> =
-                        tag_counts.into_iter().collect();
-                    // Sort by count (descending) then name (ascending)
-                    sorted_tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                        let paths = found.into_iter().map(|(path, _)| path).collect();
+                        sender.send(Message::TagsAnalyzed(paths, sorted_tags));
+                    });
+                }
+                Message::TagsAnalyzed(target_paths, sorted_tags) => {
+                    status_bar.set_label("Ready");
+                    let total_sel = target_paths.len();
 
                     let tag_names: Vec<String> =
                         sorted_tags.iter().map(|(n, _)| n.clone()).collect();
@@ -1140,7 +3702,7 @@ fn main() {
                     dialog.make_modal(true);
                     dialog.show();
 
-                    let s_clone = s.clone();
+                    let s_clone = s;
                     let mut d_clone = dialog.clone();
                     btn_cancel.set_callback(move |_| d_clone.hide());
 
@@ -1158,13 +3720,13 @@ fn main() {
                         };
 
                         if !target.is_empty() {
-                            s_clone.send(Message::DoUpdateTag(target));
+                            s_clone.send(Message::DoUpdateTag(target_paths.clone(), target));
                             d_clone2.hide();
                         }
                     });
                 }
-                Message::DoUpdateTag(target_tag) => {
-                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap());
+                Message::DoUpdateTag(paths, target_tag) => {
+                    let sel = repos_by_path(&app_state.lock().unwrap(), &paths);
                     if sel.is_empty() {
                         continue;
                     }
@@ -1179,177 +3741,3321 @@ fn main() {
                         ));
                     }
 
-                    thread::spawn(move || {
-                        sel.par_iter().for_each(|repo| {
-                            let mut r = repo.clone();
-                            let res = r.update_to_tag(&target_tag);
-                            r.refresh();
-                            match res {
-                                Ok(_) => {
-                                    r.last_status = "Updated".to_string();
-                                    sender.send(Message::RepoUpdated(r));
-                                }
-                                Err(e) => {
-                                    r.last_status = format!("Error: {}", e);
-                                    sender.send(Message::RepoUpdated(r));
-                                }
-                            }
-                        });
-                        sender.send(Message::SetGlobalStatus("Ready".into()));
-                    });
+                    spawn_batch(sel, sender, move |r| r.update_to_tag(&target_tag).map(|_| "Updated".to_string()));
                 }
-                Message::Commit => {
-                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap());
+                Message::CreateTag => {
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) = confirm_targets("Tag", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
                     if sel.is_empty() {
-                        dialog::alert(
-                            200,
-                            200,
-                            "Please select at least one repository for commit.",
-                        );
+                        status_bar.set_label("Select repositories to tag");
                         continue;
                     }
 
-                    if let Some(msg_txt) = dialog::input(200, 200, "Commit message:", "") {
-                        if !msg_txt.is_empty() {
-                            let sender = s.clone();
+                    let Some(tag_name) = dialog::input(200, 200, "Tag name:", "") else {
+                        continue;
+                    };
+                    let tag_name = tag_name.trim().to_string();
+                    if tag_name.is_empty() {
+                        continue;
+                    }
 
-                            for repo in &sel {
-                                sender.send(Message::SetStatus(
-                                    repo.path.clone(),
-                                    "Committing...".to_string(),
-                                ));
-                            }
+                    status_bar.set_label(&format!("Tagging {}...", tag_name));
+                    let sender = s.clone();
 
-                            thread::spawn(move || {
-                                sel.par_iter().for_each(|repo| {
-                                    let mut updated_repo = repo.clone();
-                                    let res = updated_repo.commit(&msg_txt);
-                                    updated_repo.refresh();
-
-                                    match res {
-                                        Ok(_) => {
-                                            updated_repo.last_status = "Committed".to_string();
-                                            sender.send(Message::RepoUpdated(updated_repo));
-                                        }
-                                        Err(e) => {
-                                            updated_repo.last_status = format!("Error: {}", e);
-                                            sender.send(Message::RepoUpdated(updated_repo));
-                                        }
-                                    }
-                                });
-                                sender.send(Message::SetGlobalStatus("Ready".into()));
-                            });
-                        }
+                    for r in &sel {
+                        sender.send(Message::SetStatus(
+                            r.path.clone(),
+                            "Tagging...".to_string(),
+                        ));
                     }
-                }
-                Message::SetGlobalStatus(msg) => {
-                    status_bar.set_label(&msg);
-                }
-            }
-        }
-    }
-}
 
-fn scan_repositories(dirs: Vec<PathBuf>, sender: app::Sender<Message>) {
-    sender.send(Message::SetGlobalStatus("Walking directories...".into()));
-    let mut found_repos = Vec::new();
-
-    // We can't par_iter WalkDir obviously, but we can notify progress.
-    // Iteration is fast enough usually.
-    for dir in dirs {
-        sender.send(Message::SetGlobalStatus(format!(
-            "Walking {}...",
-            dir.display()
-        )));
-        for entry in WalkDir::new(&dir).into_iter().filter_map(|e| e.ok()) {
-            if entry.file_type().is_dir() && entry.file_name() == ".hg" {
-                if let Some(parent) = entry.path().parent() {
-                    found_repos.push(parent.to_path_buf());
+                    spawn_recorded_batch(sel, sender, None, move |r| {
+                        r.create_tag(&tag_name)?;
+                        Ok(RepoOutcome { status: format!("Tagged {}", tag_name), transaction: Transaction::Made("tag commit") })
+                    });
                 }
-            }
-        }
-    }
+                Message::OpenNewBranch => {
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) = confirm_targets("New Branch", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
+                    if sel.is_empty() {
+                        status_bar.set_label("Select repositories to create the branch in");
+                        continue;
+                    }
 
-    sender.send(Message::SetGlobalStatus(format!(
-        "Analyzing {} repositories...",
-        found_repos.len()
-    )));
+                    let target_paths: Vec<PathBuf> = sel.iter().map(|r| r.path.clone()).collect();
 
-    let valid_repos: Vec<Repository> = found_repos
-        .par_iter()
-        .map(|p| {
-            let mut r = Repository::new(p.clone());
-            r.refresh();
-            r
-        })
-        .collect();
+                    let mut dialog = Window::default()
+                        .with_size(400, 250)
+                        .with_label("New Branch");
+                    dialog.set_border(true);
+                    let mut pack = Pack::new(10, 10, 380, 230, "");
+                    pack.set_spacing(10);
 
-    sender.send(Message::ScanComplete(valid_repos));
-    sender.send(Message::SetGlobalStatus("Ready".into()));
-}
+                    pack.add(
+                        &Frame::default()
+                            .with_size(0, 20)
+                            .with_label(&format!("Branch name ({} repos):", sel.len())),
+                    );
+                    let input_name = fltk::input::Input::default().with_size(0, 30);
 
-fn update_browser(browser: &mut MultiBrowser, repos: &[Repository], show_full_path: bool) {
-    browser.clear();
+                    let check_commit = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Commit the new branch with message:");
+                    let mut input_msg = fltk::input::Input::default().with_size(0, 30);
+                    input_msg.deactivate();
 
-    for (_i, repo) in repos.iter().enumerate() {
-        let path_str = if show_full_path {
-            repo.path.display().to_string()
-        } else {
-            repo.path
-                .file_name()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .to_string()
-        };
-        let mod_str = if repo.modified { "Yes" } else { "No" };
+                    let mut status = Frame::default().with_size(0, 20);
+                    status.set_label_color(Color::Red);
 
-        let status = &repo.last_status;
+                    let btn_row = Flex::default().with_size(0, 30).row();
+                    let mut btn_cancel = Button::default().with_label("Close");
+                    let mut btn_ok = Button::default().with_label("Create");
+                    btn_row.end();
 
-        let line = format!(
-            "{}\t{}\t{}\t{}\t{}\t{}",
-            path_str, repo.current_branch, repo.revision, mod_str, repo.commit_type, status
-        );
-        browser.add(&line);
-    }
-}
+                    pack.end();
+                    dialog.end();
+                    dialog.make_modal(true);
+                    dialog.show();
 
-fn sort_repos(repos: &mut Vec<Repository>, state: &SortState) {
-    if state.order == SortOrder::None {
-        // Default sort by path
-        repos.sort_by(|a, b| a.path.cmp(&b.path));
-        return;
+                    let mut msg_c = input_msg.clone();
+                    let mut check_c = check_commit.clone();
+                    check_c.set_callback(move |c| {
+                        if c.is_checked() {
+                            msg_c.activate();
+                        } else {
+                            msg_c.deactivate();
+                        }
+                    });
+
+                    let mut d_clone = dialog.clone();
+                    btn_cancel.set_callback(move |_| d_clone.hide());
+
+                    let s_clone = s.clone();
+                    let mut d_clone2 = dialog.clone();
+                    btn_ok.set_callback(move |_| {
+                        let name = input_name.value().trim().to_string();
+                        if name.is_empty() {
+                            status.set_label("Enter a branch name");
+                            return;
+                        }
+                        let commit_msg = if check_commit.is_checked() {
+                            let msg_txt = input_msg.value().trim().to_string();
+                            if msg_txt.is_empty() {
+                                status.set_label("Enter a commit message");
+                                return;
+                            }
+                            Some(msg_txt)
+                        } else {
+                            None
+                        };
+                        s_clone.send(Message::NewBranch(target_paths.clone(), name, commit_msg));
+                        d_clone2.hide();
+                    });
+                }
+                Message::NewBranch(paths, name, commit_msg) => {
+                    let sel = repos_by_path(&app_state.lock().unwrap(), &paths);
+                    if sel.is_empty() {
+                        continue;
+                    }
+
+                    status_bar.set_label("Creating branch...");
+                    let sender = s.clone();
+
+                    for repo in &sel {
+                        sender.send(Message::SetStatus(
+                            repo.path.clone(),
+                            "Creating branch...".to_string(),
+                        ));
+                    }
+
+                    spawn_recorded_batch(sel, sender, None, move |r| {
+                        r.create_branch(&name)?;
+                        // Dirty repos are never committed here, that would sweep up unrelated changes
+                        if r.modified {
+                            return Ok(format!("Branch {} set, takes effect at next commit (uncommitted changes)", name).into());
+                        }
+                        match &commit_msg {
+                            Some(msg_txt) => {
+                                r.commit(msg_txt)?;
+                                Ok(RepoOutcome { status: format!("Branch {} created", name), transaction: Transaction::Made("commit") })
+                            }
+                            None => Ok(format!("Branch {} set, takes effect at next commit", name).into()),
+                        }
+                    });
+                }
+                Message::Commit => {
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) =
+                        confirm_targets("Commit", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
+                    if sel.is_empty() {
+                        dialog::alert(
+                            200,
+                            200,
+                            "Please select at least one repository for commit.",
+                        );
+                        continue;
+                    }
+
+                    // hg aborts commits without ui.username; offer to set it before going further
+                    let no_username: Vec<&Repository> =
+                        sel.iter().filter(|r| r.username_set == Some(false)).collect();
+                    if !no_username.is_empty() {
+                        let names: Vec<String> = no_username
+                            .iter()
+                            .take(10)
+                            .map(|r| r.path.display().to_string())
+                            .collect();
+                        let question = format!(
+                            "No username (ui.username) is configured for {} repositories:\n{}\n\nThey will not be committed unless a username is set.",
+                            no_username.len(),
+                            names.join("\n")
+                        );
+                        match dialog::choice2(200, 200, &question, "Cancel", "Set Username...", "Continue") {
+                            Some(1) => {
+                                let Some(name) = dialog::input_default(
+                                    "Username to write to each repository's .hg/hgrc\n(e.g. Jane Doe <jane@example.com>):",
+                                    "",
+                                ) else {
+                                    continue;
+                                };
+                                let name = name.trim().to_string();
+                                if name.is_empty() {
+                                    continue;
+                                }
+                                let mut repos = app_state.lock().unwrap();
+                                for r in &no_username {
+                                    if let Err(e) = r.set_local_username(&name) {
+                                        dialog::alert(200, 200, &format!("{}: {}", r.path.display(), e));
+                                        continue;
+                                    }
+                                    if let Some(stored) = repos.iter_mut().find(|x| x.path == r.path) {
+                                        stored.username_set = Some(true);
+                                    }
+                                }
+                            }
+                            Some(2) => {}
+                            _ => continue,
+                        }
+                    }
+
+                    // hg refuses to commit dirty subrepositories unless ui.commitsubrepos is set
+                    let with_subrepos = sel.iter().filter(|r| r.has_subrepos()).count();
+                    if with_subrepos > 0 {
+                        let question = format!(
+                            "{} of the selected repositories have subrepositories.\n\nChanges inside a subrepository are not part of this commit: commit them in the subrepository first, or hg may abort with \"uncommitted changes in subrepository\".",
+                            with_subrepos
+                        );
+                        if dialog::choice2(200, 200, &question, "Cancel", "Continue", "") != Some(1) {
+                            continue;
+                        }
+                    }
+
+                    let target_paths: Vec<PathBuf> = sel.iter().map(|r| r.path.clone()).collect();
+
+                    let mut dialog = Window::default()
+                        .with_size(400, 240)
+                        .with_label("Commit");
+                    dialog.set_border(true);
+                    let mut pack = Pack::new(10, 10, 380, 220, "");
+                    pack.set_spacing(10);
+
+                    pack.add(
+                        &Frame::default()
+                            .with_size(0, 20)
+                            .with_label(&format!("Commit message ({} repos):", sel.len())),
+                    );
+                    let mut input = fltk::input::Input::default().with_size(0, 30);
+                    if let Some(template) = load_config().default_commit_message {
+                        input.set_value(&template);
+                    }
+                    let mut placeholders = Frame::default()
+                        .with_size(0, 20)
+                        .with_label("Placeholders: {branch} current branch, {name} repository name");
+                    placeholders.set_label_size(12);
+                    placeholders.set_align(fltk::enums::Align::Left | fltk::enums::Align::Inside);
+
+                    let check_addremove = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Include new/missing files (addremove)");
+
+                    let check_secret = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Commit as secret (never pushed)");
+
+                    let btn_row = Flex::default().with_size(0, 30).row();
+                    let mut btn_cancel = Button::default().with_label("Close");
+                    let mut btn_ok = Button::default().with_label("Commit");
+                    btn_row.end();
+
+                    pack.end();
+                    dialog.end();
+                    dialog.make_modal(true);
+                    dialog.show();
+                    let _ = input.take_focus();
+
+                    let mut d_clone = dialog.clone();
+                    btn_cancel.set_callback(move |_| d_clone.hide());
+
+                    let s_clone = s.clone();
+                    let mut d_clone2 = dialog.clone();
+                    btn_ok.set_callback(move |_| {
+                        let msg_txt = input.value();
+                        // hg aborts on an empty message; keep the dialog open to type one
+                        if msg_txt.trim().is_empty() {
+                            dialog::alert(200, 200, "Commit message cannot be empty.");
+                            let _ = input.take_focus();
+                            return;
+                        }
+                        s_clone.send(Message::DoCommit(
+                            target_paths.clone(),
+                            msg_txt,
+                            check_addremove.is_checked(),
+                            CommitOptions { secret: check_secret.is_checked() },
+                        ));
+                        d_clone2.hide();
+                    });
+                }
+                Message::DoCommit(paths, msg_txt, addremove, options) => {
+                    let sel = repos_by_path(&app_state.lock().unwrap(), &paths);
+                    if sel.is_empty() {
+                        continue;
+                    }
+
+                    status_bar.set_label("Committing...");
+                    let sender = s.clone();
+
+                    for repo in &sel {
+                        sender.send(Message::SetStatus(
+                            repo.path.clone(),
+                            "Committing...".to_string(),
+                        ));
+                    }
+
+                    spawn_recorded_batch(sel, sender, None, move |r| {
+                        if r.username_set == Some(false) {
+                            return Err(RepoError::NoUsername.into());
+                        }
+                        // A failed addremove aborts the commit for this repo
+                        let added = if addremove {
+                            r.addremove()
+                                .map_err(|e| anyhow::anyhow!("addremove failed, not committed: {}", e))?
+                        } else {
+                            0
+                        };
+                        let message = expand_commit_template(&msg_txt, r)?;
+                        r.commit_with_options(&message, &options)?;
+                        let status = if added > 0 {
+                            format!("Committed ({} new files)", added)
+                        } else {
+                            "Committed".to_string()
+                        };
+                        Ok(RepoOutcome { status, transaction: Transaction::Made("commit") })
+                    });
+                }
+                Message::OpenRevert => {
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) =
+                        confirm_targets("Revert All Changes", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
+                    if sel.is_empty() {
+                        status_bar.set_label("Select repositories to revert");
+                        continue;
+                    }
+
+                    status_bar.set_label("Collecting changes...");
+                    let sender = s;
+
+                    // Only dirty repos are reverted; clean ones are skipped silently
+                    spawn_worker(move || {
+                        let changes: Vec<(PathBuf, Vec<String>)> = sel
+                            .par_iter()
+                            .filter(|r| r.modified)
+                            .filter_map(|r| match r.get_changed_files() {
+                                Ok(files) if !files.is_empty() => Some((r.path.clone(), files)),
+                                _ => None,
+                            })
+                            .collect();
+                        sender.send(Message::RevertPreviewed(changes));
+                    });
+                }
+                Message::RevertPreviewed(changes) => {
+                    if changes.is_empty() {
+                        status_bar.set_label("No local changes to revert");
+                        continue;
+                    }
+                    status_bar.set_label("Ready");
+
+                    let mut preview = String::new();
+                    for (path, files) in &changes {
+                        preview.push_str(&format!("{}\n", path.display()));
+                        for f in files {
+                            preview.push_str(&format!("    {}\n", f));
+                        }
+                        preview.push('\n');
+                    }
+
+                    let mut dialog = Window::default()
+                        .with_size(600, 450)
+                        .with_label("Revert All Changes");
+                    dialog.set_border(true);
+                    let mut flex = Flex::new(10, 10, 580, 430, "").column();
+                    flex.set_spacing(10);
+
+                    let header = Frame::default().with_label(&format!(
+                        "The following files will be reverted in {} repositories:",
+                        changes.len()
+                    ));
+                    flex.fixed(&header, 20);
+
+                    let mut buf = fltk::text::TextBuffer::default();
+                    buf.set_text(&preview);
+                    let mut display = fltk::text::TextDisplay::default();
+                    display.set_buffer(buf);
+
+                    let check_no_backup = fltk::button::CheckButton::default()
+                        .with_label("Don't save backup copies (--no-backup)");
+                    flex.fixed(&check_no_backup, 30);
+
+                    let btn_row = Flex::default().row();
+                    let mut btn_cancel = Button::default().with_label("Cancel");
+                    let mut btn_ok = Button::default().with_label("Revert");
+                    btn_row.end();
+                    flex.fixed(&btn_row, 30);
+
+                    flex.end();
+                    dialog.end();
+                    dialog.make_modal(true);
+                    dialog.show();
+
+                    let mut d_clone = dialog.clone();
+                    btn_cancel.set_callback(move |_| d_clone.hide());
+
+                    let s_clone = s;
+                    let mut d_clone2 = dialog.clone();
+                    let paths: Vec<PathBuf> = changes.into_iter().map(|(p, _)| p).collect();
+                    btn_ok.set_callback(move |_| {
+                        s_clone.send(Message::DoRevert(paths.clone(), check_no_backup.is_checked()));
+                        d_clone2.hide();
+                    });
+                }
+                Message::OpenChangePhase => {
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) = confirm_targets("Change Phase", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
+                    if sel.is_empty() {
+                        status_bar.set_label("Select repositories to change phase");
+                        continue;
+                    }
+                    let target_paths: Vec<PathBuf> = sel.iter().map(|r| r.path.clone()).collect();
+
+                    let mut dialog = Window::default()
+                        .with_size(320, 250)
+                        .with_label("Change Phase");
+                    dialog.set_border(true);
+                    let mut pack = Pack::new(10, 10, 300, 230, "");
+                    pack.set_spacing(10);
+
+                    pack.add(
+                        &Frame::default()
+                            .with_size(0, 20)
+                            .with_label(&format!("Revisions (revset, {} repos):", sel.len())),
+                    );
+                    let mut input = fltk::input::Input::default().with_size(0, 30);
+                    input.set_value("draft()");
+
+                    pack.add(&Frame::default().with_size(0, 20).with_label("New phase:"));
+                    let mut choice = fltk::menu::Choice::default().with_size(0, 30);
+                    for phase in Phase::ALL {
+                        choice.add_choice(&phase.to_string());
+                    }
+                    choice.set_value(0);
+
+                    let check_force = fltk::button::CheckButton::default()
+                        .with_size(0, 30)
+                        .with_label("Force (allow moving back from public)");
+
+                    let btn_row = Flex::default().with_size(0, 30).row();
+                    let mut btn_cancel = Button::default().with_label("Close");
+                    let mut btn_ok = Button::default().with_label("Apply");
+                    btn_row.end();
+
+                    pack.end();
+                    dialog.end();
+                    dialog.make_modal(true);
+                    dialog.show();
+
+                    let mut d_clone = dialog.clone();
+                    btn_cancel.set_callback(move |_| d_clone.hide());
+
+                    let s_clone = s.clone();
+                    let mut d_clone2 = dialog.clone();
+                    btn_ok.set_callback(move |_| {
+                        let rev = input.value().trim().to_string();
+                        let Some(&phase) = Phase::ALL.get(choice.value() as usize) else {
+                            return;
+                        };
+                        if !rev.is_empty() {
+                            s_clone.send(Message::ChangePhase(
+                                target_paths.clone(),
+                                rev,
+                                phase,
+                                check_force.is_checked(),
+                            ));
+                            d_clone2.hide();
+                        }
+                    });
+                }
+                Message::ChangePhase(paths, rev, phase, force) => {
+                    let sel = repos_by_path(&app_state.lock().unwrap(), &paths);
+                    if sel.is_empty() {
+                        continue;
+                    }
+
+                    status_bar.set_label(&format!("Changing phase of {} to {}...", rev, phase));
+                    let sender = s.clone();
+
+                    for r in &sel {
+                        sender.send(Message::SetStatus(
+                            r.path.clone(),
+                            "Changing phase...".to_string(),
+                        ));
+                    }
+
+                    spawn_recorded_batch(sel, sender, None, move |r| {
+                        r.set_phase(&rev, phase, force)?;
+                        let status = if force {
+                            format!("Phase forced to {}", phase)
+                        } else {
+                            format!("Phase set to {}", phase)
+                        };
+                        Ok(RepoOutcome { status, transaction: Transaction::Made("phase change") })
+                    });
+                }
+                Message::ShowDiff => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    if sel.len() != 1 {
+                        status_bar.set_label("Select a single repository to show its diff");
+                        continue;
+                    }
+                    status_bar.set_label("Loading diff...");
+                    let repo = sel[0].clone();
+                    let sender = s;
+                    spawn_worker(move || {
+                        let result = repo.get_diff().map_err(|e| e.to_string());
+                        sender.send(Message::DiffLoaded(repo.path, result));
+                    });
+                }
+                Message::DiffLoaded(path, result) => {
+                    status_bar.set_label("Ready");
+                    match result {
+                        Ok(diff) if diff.is_empty() => {
+                            status_bar.set_label("No changes to show");
+                        }
+                        Ok(diff) => show_diff_window(&path, &diff),
+                        Err(e) => {
+                            dialog::alert(200, 200, &format!("Failed to get diff: {}", e));
+                        }
+                    }
+                }
+                Message::ShowGraph => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    if sel.len() != 1 {
+                        status_bar.set_label("Select a single repository to show its graph");
+                        continue;
+                    }
+                    // A single graph window, reused for whichever repo was asked for last
+                    if let Some(mut old) = graph_view.take() {
+                        old.win.hide();
+                    }
+                    graph_view = Some(show_graph_window(&sel[0].path, s.clone()));
+                    s.send(Message::LoadGraph(sel[0].path.clone(), DEFAULT_GRAPH_LIMIT));
+                }
+                Message::LoadGraph(path, limit) => {
+                    if let Some(view) = graph_view.as_mut() {
+                        view.buf.set_text("Loading...");
+                    }
+                    let sender = s;
+                    spawn_worker(move || {
+                        let result = Repository::new(path.clone())
+                            .get_graph_log(limit)
+                            .map_err(|e| e.to_string());
+                        sender.send(Message::GraphLoaded(path, result));
+                    });
+                }
+                Message::GraphLoaded(path, result) => {
+                    // The window may have been closed or reopened for another repo meanwhile
+                    if let Some(view) = graph_view.as_mut()
+                        && view.path == path
+                        && view.win.shown()
+                    {
+                        match result {
+                            Ok(text) => view.buf.set_text(&text),
+                            Err(e) => view.buf.set_text(&format!("Error: {}", e)),
+                        }
+                    }
+                }
+                Message::ShowHistory => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    if sel.len() != 1 {
+                        status_bar.set_label("Select a single repository to show its history");
+                        continue;
+                    }
+                    // A single history window, reused for whichever repo was asked for last
+                    if let Some(mut old) = history_view.take() {
+                        old.win.hide();
+                    }
+                    let path = sel[0].path.clone();
+                    history_view = Some(show_history_window(&path, s.clone()));
+                    let sender = s;
+                    spawn_worker(move || {
+                        let result = Repository::new(path.clone())
+                            .recent_log(HISTORY_LIMIT)
+                            .map_err(|e| e.to_string());
+                        sender.send(Message::HistoryLoaded(path, result));
+                    });
+                }
+                Message::HistoryLoaded(path, result) => {
+                    // The window may have been closed or reopened for another repo meanwhile
+                    if let Some(view) = history_view.as_mut()
+                        && view.path == path
+                        && view.win.shown()
+                    {
+                        view.list.clear();
+                        match result {
+                            Ok(entries) => {
+                                if entries.is_empty() {
+                                    view.list.add("No changesets");
+                                }
+                                for (rev, branch, author, summary) in &entries {
+                                    // "@." keeps the text from being parsed as format codes
+                                    view.list.add(&format!("@.{}\t@.{}\t@.{}\t@.{}", rev, branch, author, summary));
+                                }
+                                *view.revs.borrow_mut() = entries.into_iter().map(|(rev, ..)| rev).collect();
+                            }
+                            Err(e) => {
+                                view.list.add("Could not read the history");
+                                view.revs.borrow_mut().clear();
+                                status_bar.set_label(&format!("Error: {}", e));
+                            }
+                        }
+                    }
+                }
+                Message::ShowConflicts => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    if sel.len() != 1 {
+                        status_bar.set_label("Select a single repository to resolve its conflicts");
+                        continue;
+                    }
+                    if let Some(mut old) = resolve_view.take() {
+                        old.win.hide();
+                    }
+                    resolve_view = Some(show_resolve_window(&sel[0].path, s.clone()));
+                    s.send(Message::LoadConflicts(sel[0].path.clone()));
+                }
+                Message::LoadConflicts(path) => {
+                    let sender = s;
+                    spawn_worker(move || {
+                        let result = Repository::new(path.clone())
+                            .list_unresolved()
+                            .map_err(|e| e.to_string());
+                        sender.send(Message::ConflictsLoaded(path, result));
+                    });
+                }
+                Message::ConflictsLoaded(path, result) => {
+                    let Some(view) = resolve_view.as_mut().filter(|v| v.path == path && v.win.shown()) else {
+                        continue;
+                    };
+                    view.list.clear();
+                    match result {
+                        Ok(files) => {
+                            // Only once the last file is resolved here, not when
+                            // the window opens on a repo without conflicts
+                            if files.is_empty() && !view.files.is_empty() {
+                                let mut repos = app_state.lock().unwrap();
+                                if let Some(r) = repos.iter_mut().find(|r| r.path == path) {
+                                    r.last_status = "Resolved".to_string();
+                                }
+                                let selection = selected_paths(&browser, &repos, &prefs);
+                                update_browser(&mut browser, &repos, &prefs, &selection);
+                            }
+                            if files.is_empty() {
+                                view.list.add("No unresolved files");
+                            }
+                            for f in &files {
+                                view.list.add(&format!("@.{}", f));
+                            }
+                            view.files = files;
+                        }
+                        Err(e) => {
+                            view.list.add("Could not list the unresolved files");
+                            view.files.clear();
+                            status_bar.set_label(&format!("Error: {}", e));
+                        }
+                    }
+                }
+                Message::ResolveFile(path, file, mark) => {
+                    let sender = s;
+                    if refuse_when_queue_full(&[], sender) {
+                        continue;
+                    }
+                    status_bar.set_label(if mark { "Marking resolved..." } else { "Running merge tool..." });
+                    // Writes to the repository, so it waits for the batches running there
+                    queue_batch(move || {
+                        let repo = Repository::new(path.clone());
+                        let result = if mark { repo.mark_resolved(&file) } else { repo.run_merge_tool(&file) };
+                        let status = match result {
+                            Ok(_) => format!("Resolved {}", file),
+                            Err(e) => format!("Error resolving {}: {}", file, e),
+                        };
+                        sender.send(Message::SetGlobalStatus(status));
+                        sender.send(Message::LoadConflicts(path));
+                    });
+                }
+                Message::OpenEditPaths => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    if sel.len() != 1 {
+                        status_bar.set_label("Select a single repository to edit its remote paths");
+                        continue;
+                    }
+                    let repo = &sel[0];
+                    match repo.get_paths() {
+                        Ok(paths) => show_paths_window(&repo.path, &paths, s.clone()),
+                        Err(e) => {
+                            dialog::alert(200, 200, &format!("Cannot edit remote paths: {}", e));
+                        }
+                    }
+                }
+                Message::SavePaths(path, entries) => {
+                    let repo = Repository::new(path);
+                    let mut saved = 0;
+                    for (name, url) in &entries {
+                        if let Err(e) = repo.set_path(name, url) {
+                            dialog::alert(200, 200, &format!("Failed to save path '{}': {}", name, e));
+                            break;
+                        }
+                        saved += 1;
+                    }
+                    status_bar.set_label(&format!("Saved {} remote paths", saved));
+                }
+                Message::OpenEditIgnore => {
+                    let sel = get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs);
+                    let Some(repo) = sel.first() else {
+                        status_bar.set_label("Select a repository to edit its .hgignore");
+                        continue;
+                    };
+                    match repo.read_hgignore() {
+                        Ok(text) => show_hgignore_window(&repo.path, &text, sel.len(), s.clone()),
+                        Err(e) => dialog::alert(200, 200, &format!("Cannot edit .hgignore: {}", e)),
+                    }
+                }
+                Message::SaveIgnore(path, text) => {
+                    let targets = repos_by_path(&app_state.lock().unwrap(), &[path]);
+                    status_bar.set_label("Saving .hgignore...");
+                    spawn_batch(targets, s.clone(), move |r| {
+                        r.write_hgignore(&text).map(|_| "Saved .hgignore".to_string())
+                    });
+                }
+                Message::DoRevert(paths, no_backup) => {
+                    let targets = repos_by_path(&app_state.lock().unwrap(), &paths);
+                    if targets.is_empty() {
+                        continue;
+                    }
+
+                    status_bar.set_label("Reverting...");
+                    let sender = s.clone();
+
+                    for r in &targets {
+                        sender.send(Message::SetStatus(
+                            r.path.clone(),
+                            "Reverting...".to_string(),
+                        ));
+                    }
+
+                    // Rolling back afterwards would undo an earlier commit, not the revert
+                    spawn_recorded_batch(targets, sender, None, move |r| {
+                        r.revert_changes(no_backup)?;
+                        Ok(RepoOutcome { status: "Reverted".to_string(), transaction: Transaction::Cleared })
+                    });
+                }
+                Message::UndoLast => {
+                    let Some(transactions) = undoable.take() else {
+                        dialog::message(
+                            200,
+                            200,
+                            "Nothing to undo.\n\nOnly the last commit, tag, phase change, pull or sync run from ManaHg can be rolled back,\nand only in the repositories where it committed or brought changesets.\nReverts, clean updates, aborts and purges cannot: reverted files are kept as .orig\nbackups unless \"no backup\" was checked.",
+                        );
+                        continue;
+                    };
+                    let paths: Vec<PathBuf> = transactions.iter().map(|(path, _)| path.clone()).collect();
+                    let targets = repos_by_path(&app_state.lock().unwrap(), &paths);
+                    let question = format!(
+                        "Roll back {} (hg rollback)?\nOnly the most recent transaction of each repository can be undone.",
+                        describe_transactions(&transactions)
+                    );
+                    if targets.is_empty()
+                        || dialog::choice2(200, 200, &question, "Cancel", "Roll Back", "") != Some(1)
+                    {
+                        undoable = Some(transactions);
+                        continue;
+                    }
+
+                    status_bar.set_label("Rolling back...");
+                    let sender = s.clone();
+
+                    for r in &targets {
+                        sender.send(Message::SetStatus(r.path.clone(), "Rolling back...".to_string()));
+                    }
+
+                    spawn_batch(targets, sender, |r| r.rollback().map(|_| "Rolled back".to_string()));
+                }
+                Message::AbortInterrupted => {
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) = confirm_targets("Abort Interrupted Operation", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
+                    let interrupted: Vec<Repository> =
+                        sel.into_iter().filter(|r| r.interrupted.is_some()).collect();
+                    if interrupted.is_empty() {
+                        status_bar.set_label("No interrupted operation in the selected repositories");
+                        continue;
+                    }
+
+                    let mut question = format!(
+                        "Abort the unfinished operation in {} repositories?\nChanges made by a merge in progress are discarded.\n\n",
+                        interrupted.len()
+                    );
+                    for r in &interrupted {
+                        if let Some(op) = r.interrupted {
+                            question.push_str(&format!("{}: {}\n", r.path.display(), op));
+                        }
+                    }
+                    if dialog::choice2(200, 200, &question, "Cancel", "Abort Operations", "") != Some(1) {
+                        continue;
+                    }
+
+                    status_bar.set_label("Aborting...");
+                    let sender = s.clone();
+                    for r in &interrupted {
+                        sender.send(Message::SetStatus(r.path.clone(), "Aborting...".to_string()));
+                    }
+                    // An aborted rebase or graft strips what it had committed
+                    spawn_recorded_batch(interrupted, sender, None, |r| {
+                        r.abort_interrupted()?;
+                        Ok(RepoOutcome { status: "Aborted".to_string(), transaction: Transaction::Cleared })
+                    });
+                }
+                Message::OpenPurge => {
+                    let repos = app_state.lock().unwrap().clone();
+                    let Some(sel) =
+                        confirm_targets("Purge Untracked Files", &browser, &repos, &prefs)
+                    else {
+                        continue;
+                    };
+                    if sel.is_empty() {
+                        status_bar.set_label("Select repositories to purge");
+                        continue;
+                    }
+
+                    status_bar.set_label("Collecting untracked files...");
+                    let sender = s;
+
+                    // (path, untracked, ignored) for each repo with something to delete. Whether
+                    // purge is available is only known by running it: it is a core command since hg 5.7
+                    spawn_worker(move || {
+                        let counts: Vec<(PathBuf, usize, usize)> = sel
+                            .par_iter()
+                            .filter_map(|r| {
+                                let files = r.get_untracked_files().ok()?;
+                                let ignored = files.iter().filter(|f| f.starts_with('I')).count();
+                                let untracked = files.len() - ignored;
+                                (!files.is_empty()).then(|| (r.path.clone(), untracked, ignored))
+                            })
+                            .collect();
+                        sender.send(Message::PurgePreviewed(counts));
+                    });
+                }
+                Message::PurgePreviewed(counts) => {
+                    if counts.is_empty() {
+                        status_bar.set_label("No untracked files to purge");
+                        continue;
+                    }
+                    status_bar.set_label("Ready");
+
+                    let mut preview = String::new();
+                    for (path, untracked, ignored) in &counts {
+                        preview.push_str(&format!(
+                            "{}\n    {} untracked files, {} ignored files (only with --all)\n",
+                            path.display(),
+                            untracked,
+                            ignored
+                        ));
+                    }
+
+                    let mut dialog = Window::default()
+                        .with_size(600, 450)
+                        .with_label("Purge Untracked Files");
+                    dialog.set_border(true);
+                    let mut flex = Flex::new(10, 10, 580, 430, "").column();
+                    flex.set_spacing(10);
+
+                    let mut header = Frame::default().with_label(&format!(
+                        "Untracked files will be PERMANENTLY DELETED in {} repositories.\nThey are not backed up and cannot be recovered.",
+                        counts.len()
+                    ));
+                    header.set_label_color(Color::Red);
+                    flex.fixed(&header, 40);
+
+                    let mut buf = fltk::text::TextBuffer::default();
+                    buf.set_text(&preview);
+                    let mut display = fltk::text::TextDisplay::default();
+                    display.set_buffer(buf);
+
+                    let check_all = fltk::button::CheckButton::default()
+                        .with_label("Also delete ignored files (--all)");
+                    flex.fixed(&check_all, 30);
+
+                    let btn_row = Flex::default().row();
+                    let mut btn_cancel = Button::default().with_label("Cancel");
+                    let mut btn_ok = Button::default().with_label("Delete Files");
+                    btn_row.end();
+                    flex.fixed(&btn_row, 30);
+
+                    flex.end();
+                    dialog.end();
+                    dialog.make_modal(true);
+                    dialog.show();
+
+                    let mut d_clone = dialog.clone();
+                    btn_cancel.set_callback(move |_| d_clone.hide());
+
+                    let s_clone = s.clone();
+                    let mut d_clone2 = dialog.clone();
+                    let paths: Vec<PathBuf> = counts.into_iter().map(|(p, _, _)| p).collect();
+                    btn_ok.set_callback(move |_| {
+                        // Repos where hg does not know purge come back as OfferEnablePurge
+                        s_clone.send(Message::DoPurge(paths.clone(), check_all.is_checked()));
+                        d_clone2.hide();
+                    });
+                }
+                Message::DoPurge(paths, include_ignored) => {
+                    let targets = repos_by_path(&app_state.lock().unwrap(), &paths);
+                    if targets.is_empty() {
+                        continue;
+                    }
+
+                    status_bar.set_label("Purging...");
+                    let sender = s;
+
+                    for r in &targets {
+                        sender.send(Message::SetStatus(r.path.clone(), "Purging...".to_string()));
+                    }
+
+                    // Repos where hg does not know purge, to offer enabling the extension once done
+                    let disabled = Arc::new(Mutex::new(Vec::new()));
+                    let found = disabled.clone();
+                    spawn_recorded_batch_then(
+                        targets,
+                        sender,
+                        None,
+                        move |r| match r.purge(include_ignored) {
+                            Ok(_) => Ok(RepoOutcome { status: "Purged".to_string(), transaction: Transaction::Cleared }),
+                            Err(e) => {
+                                if matches!(e.downcast_ref(), Some(RepoError::ExtensionDisabled(_))) {
+                                    found.lock().unwrap().push(r.path.clone());
+                                }
+                                Err(e)
+                            }
+                        },
+                        move || {
+                            let disabled = std::mem::take(&mut *disabled.lock().unwrap());
+                            if !disabled.is_empty() {
+                                sender.send(Message::OfferEnablePurge(disabled, include_ignored));
+                            }
+                        },
+                    );
+                }
+                Message::OfferEnablePurge(paths, include_ignored) => {
+                    let question = format!(
+                        "The purge extension is not enabled in {} repositories.\n\nEnable it in their .hg/hgrc and purge again?",
+                        paths.len()
+                    );
+                    if dialog::choice2(200, 200, &question, "Cancel", "Enable and Purge", "") != Some(1) {
+                        continue;
+                    }
+                    let targets = repos_by_path(&app_state.lock().unwrap(), &paths);
+                    let mut enabled = Vec::new();
+                    for r in &targets {
+                        match r.enable_extension("purge") {
+                            Ok(()) => enabled.push(r.path.clone()),
+                            Err(e) => s.send(Message::SetStatus(r.path.clone(), format!("Error: {}", e))),
+                        }
+                    }
+                    if !enabled.is_empty() {
+                        s.send(Message::DoPurge(enabled, include_ignored));
+                    }
+                }
+                Message::ToggleStatusPanel => {
+                    prefs.show_status_panel = !prefs.show_status_panel;
+                    if prefs.show_status_panel {
+                        status_panel.show();
+                        // Load the file list for the current selection
+                        s.send(Message::SelectionChanged);
+                    } else {
+                        status_panel.hide();
+                    }
+                    flex.layout();
+                    save_config(&app_state.lock().unwrap(), &prefs);
+                }
+                Message::ToggleDetailsPanel => {
+                    prefs.show_details_panel = !prefs.show_details_panel;
+                    if prefs.show_details_panel {
+                        splitter.show();
+                        details_panel.show();
+                        s.send(Message::SelectionChanged);
+                    } else {
+                        splitter.hide();
+                        details_panel.hide();
+                        details_path = None;
+                    }
+                    main_row.layout();
+                    save_config(&app_state.lock().unwrap(), &prefs);
+                }
+                Message::DetailsPanelResized(width) => {
+                    prefs.details_panel_width = width;
+                    save_config(&app_state.lock().unwrap(), &prefs);
+                }
+                Message::DetailsLoaded(path, details) => {
+                    // Ignore results for a repo that is no longer focused
+                    if details_path.as_ref() != Some(&path) {
+                        continue;
+                    }
+                    let repos = app_state.lock().unwrap();
+                    if let Some(repo) = repos.iter().find(|r| r.path == path) {
+                        let (note, files) = details_text(repo, &details);
+                        fill_status_panel(&mut details_panel, &files, &note);
+                    }
+                }
+                Message::ToggleModifiedOnly => {
+                    let repos = app_state.lock().unwrap();
+                    let selection = selected_paths(&browser, &repos, &prefs);
+                    prefs.modified_only = !prefs.modified_only;
+                    save_config(&repos, &prefs);
+                    update_browser(&mut browser, &repos, &prefs, &selection);
+                    if prefs.modified_only {
+                        let shown = repos.iter().filter(|r| shown_in_list(r, &prefs)).count();
+                        status_bar.set_label(&format!("{} modified of {}", shown, repos.len()));
+                    } else {
+                        status_bar.set_label(&format!("{} repositories", repos.len()));
+                    }
+                }
+                Message::ToggleGroupByParent => {
+                    let repos = app_state.lock().unwrap();
+                    // Header rows shift the lines, so capture the selection with the old layout
+                    let selection = selected_paths(&browser, &repos, &prefs);
+                    prefs.group_by_parent = !prefs.group_by_parent;
+                    save_config(&repos, &prefs);
+                    update_browser(&mut browser, &repos, &prefs, &selection);
+                }
+                Message::StatusFilesLoaded(path, result) => {
+                    // Ignore results for a repo that is no longer focused
+                    if status_panel_path.as_ref() != Some(&path) {
+                        continue;
+                    }
+                    let sel_count =
+                        get_selected_repos(&browser, &app_state.lock().unwrap(), &prefs).len();
+                    let mut note = format!("{}", path.display());
+                    if sel_count > 1 {
+                        note.push_str(&format!(
+                            " (+{} other selected repositories not shown)",
+                            sel_count - 1
+                        ));
+                    }
+                    match result {
+                        Ok(files) if files.is_empty() => {
+                            note.push_str("\n\nNo changes");
+                            fill_status_panel(&mut status_panel, &[], &note);
+                        }
+                        Ok(files) => fill_status_panel(&mut status_panel, &files, &note),
+                        Err(e) => {
+                            note.push_str(&format!("\n\nError: {}", e));
+                            fill_status_panel(&mut status_panel, &[], &note);
+                        }
+                    }
+                }
+                Message::ToggleLegend => {
+                    match legend_win.take() {
+                        Some(mut w) if w.shown() => w.hide(),
+                        _ => legend_win = Some(show_legend(&wind, &prefs)),
+                    }
+                }
+                Message::SetGlobalStatus(msg) => {
+                    status_bar.set_label(&msg);
+                }
+                Message::BatchComplete(summary) => {
+                    status_bar.set_label(&summary.status);
+                    if summary.undo_cleared {
+                        undoable = None;
+                    }
+                    if !summary.transactions.is_empty() {
+                        undoable = Some(summary.transactions.clone());
+                    }
+                    if prefs.show_batch_summary && !summary.failed.is_empty() {
+                        show_batch_summary(&summary, s.clone());
+                    }
+                }
+                Message::CancelBatches => {
+                    status_bar.set_label("Cancelling...");
+                    cancel_batches();
+                }
+                Message::OperationProgress(done, total) => {
+                    progress_done += done;
+                    progress_total += total;
+                    if progress_done >= progress_total {
+                        progress_done = 0;
+                        progress_total = 0;
+                        progress.hide();
+                        btn_cancel.hide();
+                    } else {
+                        progress.set_maximum(progress_total as f64);
+                        progress.set_value(progress_done as f64);
+                        progress.set_label(&format!("{} / {}", progress_done, progress_total));
+                        progress.show();
+                        btn_cancel.show();
+                    }
+                    status_row.layout();
+                }
+            }
+        }
+    }
+}
+
+/// Styles of the file status panel, indexed by the chars used in `fill_status_panel`.
+fn status_style_table() -> Vec<fltk::text::StyleTableEntry> {
+    let entry = |color: Color| fltk::text::StyleTableEntry {
+        color,
+        font: fltk::enums::Font::Courier,
+        size: 12,
+    };
+    vec![
+        entry(Color::Foreground),            // A: note / unknown code
+        entry(Color::from_rgb(0, 100, 200)), // B: M modified
+        entry(Color::from_rgb(0, 150, 0)),   // C: A added
+        entry(Color::from_rgb(200, 0, 0)),   // D: R removed
+        entry(Color::from_rgb(128, 128, 128)), // E: ? untracked
+        entry(Color::from_rgb(180, 100, 0)), // F: ! missing
+    ]
+}
+
+/// Fills the file status panel with a header `note` followed by one line per file.
+fn fill_status_panel(panel: &mut fltk::text::TextDisplay, files: &[(char, String)], note: &str) {
+    let mut text = String::new();
+    let mut styles = String::new();
+    if !note.is_empty() {
+        text.push_str(note);
+        text.push('\n');
+        styles.extend(std::iter::repeat_n('A', note.len() + 1));
+    }
+    for (code, file) in files {
+        let line = format!("{} {}\n", code, file);
+        let style = match code {
+            'M' => 'B',
+            'A' => 'C',
+            'R' => 'D',
+            '?' => 'E',
+            '!' => 'F',
+            _ => 'A',
+        };
+        styles.extend(std::iter::repeat_n(style, line.len()));
+        text.push_str(&line);
+    }
+    if let Some(mut buf) = panel.buffer() {
+        buf.set_text(&text);
+    }
+    if let Some(mut style_buf) = panel.style_buffer() {
+        style_buf.set_text(&styles);
+    }
+    panel.redraw();
+}
+
+/// Editor for the `[paths]` section of one repository's `.hg/hgrc`. Existing
+/// names are fixed, their URLs editable; "Add" appends a row for a new path.
+/// Only changed or new entries are sent back in `SavePaths`.
+fn show_paths_window(repo_path: &std::path::Path, paths: &[(String, String)], sender: app::Sender<Message>) {
+    let mut dialog = Window::default()
+        .with_size(600, 350)
+        .with_label("Edit Remote Paths");
+    dialog.set_border(true);
+
+    Frame::new(10, 10, 580, 20, "")
+        .with_label(&format!("Paths of {}", repo_path.display()).replace('@', "@@"));
+
+    let mut scroll = fltk::group::Scroll::new(10, 40, 580, 250, "");
+    let mut pack = Pack::new(10, 40, 560, 0, "");
+    pack.set_spacing(5);
+
+    struct PathRow {
+        name: fltk::input::Input,
+        url: fltk::input::Input,
+        /// URL when the dialog opened, `None` for added rows
+        original: Option<String>,
+    }
+    let rows: Rc<RefCell<Vec<PathRow>>> = Rc::new(RefCell::new(Vec::new()));
+    let add_row = {
+        let rows = rows.clone();
+        move |pack: &mut Pack, name: &str, url: Option<&str>| {
+            pack.begin();
+            let mut row = Flex::default().with_size(0, 30).row();
+            let mut name_input = fltk::input::Input::default();
+            name_input.set_value(name);
+            let mut url_input = fltk::input::Input::default();
+            url_input.set_value(url.unwrap_or_default());
+            row.end();
+            row.fixed(&name_input, 140);
+            if url.is_some() {
+                name_input.deactivate();
+            }
+            pack.end();
+            rows.borrow_mut().push(PathRow {
+                name: name_input,
+                url: url_input,
+                original: url.map(|u| u.to_string()),
+            });
+        }
+    };
+    for (name, url) in paths {
+        add_row(&mut pack, name, Some(url));
+    }
+    if paths.is_empty() {
+        add_row(&mut pack, "default", None);
+    }
+    pack.end();
+    scroll.end();
+
+    let mut btn_add = Button::new(10, 305, 100, 30, "Add");
+    let mut btn_cancel = Button::new(380, 305, 100, 30, "Cancel");
+    let mut btn_ok = Button::new(490, 305, 100, 30, "Save");
+
+    dialog.end();
+    dialog.make_modal(true);
+    dialog.show();
+
+    let mut pack_c = pack.clone();
+    btn_add.set_callback(move |_| {
+        add_row(&mut pack_c, "", None);
+        scroll.redraw();
+    });
+
+    let mut d_clone = dialog.clone();
+    btn_cancel.set_callback(move |_| d_clone.hide());
+
+    let mut d_clone2 = dialog.clone();
+    let repo_path = repo_path.to_path_buf();
+    btn_ok.set_callback(move |_| {
+        let mut entries = Vec::new();
+        for row in rows.borrow().iter() {
+            let name = row.name.value().trim().to_string();
+            let url = row.url.value().trim().to_string();
+            if name.is_empty() || url.is_empty() || row.original.as_deref() == Some(url.as_str()) {
+                continue;
+            }
+            if name.contains(['=', '[', ']']) || name.contains(char::is_whitespace) {
+                dialog::alert(200, 200, &format!("Invalid path name: {}", name));
+                return;
+            }
+            entries.push((name, url));
+        }
+        if !entries.is_empty() {
+            sender.send(Message::SavePaths(repo_path.clone(), entries));
+        }
+        d_clone2.hide();
+    });
+}
+
+/// Editor for the `.hgignore` of `repo_path`; `selected` is how many repositories
+/// were selected, since only the first one is edited.
+fn show_hgignore_window(repo_path: &Path, text: &str, selected: usize, sender: app::Sender<Message>) {
+    let mut title = format!("Edit .hgignore - {}", repo_path.display());
+    if selected > 1 {
+        title.push_str(&format!(" (first of {} selected)", selected));
+    }
+    let mut dialog = Window::default().with_size(600, 450).with_label(&title);
+    dialog.set_border(true);
+    let mut flex = Flex::new(10, 10, 580, 430, "").column();
+    flex.set_spacing(10);
+
+    let mut buf = fltk::text::TextBuffer::default();
+    buf.set_text(text);
+    let mut editor = fltk::text::TextEditor::default();
+    editor.set_buffer(buf.clone());
+    editor.set_text_font(fltk::enums::Font::Courier);
+
+    let btn_row = Flex::default().row();
+    let mut btn_cancel = Button::default().with_label("Cancel");
+    let mut btn_ok = Button::default().with_label("Save");
+    btn_row.end();
+    flex.fixed(&btn_row, 30);
+
+    flex.end();
+    dialog.end();
+    dialog.make_modal(true);
+    dialog.show();
+
+    let mut d_clone = dialog.clone();
+    btn_cancel.set_callback(move |_| d_clone.hide());
+
+    let mut d_clone2 = dialog.clone();
+    let repo_path = repo_path.to_path_buf();
+    btn_ok.set_callback(move |_| {
+        sender.send(Message::SaveIgnore(repo_path.clone(), buf.text()));
+        d_clone2.hide();
+    });
+}
+
+/// Lists the outgoing changesets of each repository with a checkbox to leave it
+/// out, and sends `DoPush` with the checked ones.
+fn show_push_confirmation(summaries: &[OutgoingSummary], sender: app::Sender<Message>) {
+    let mut dialog = Window::default()
+        .with_size(650, 500)
+        .with_label("Push");
+    dialog.set_border(true);
+
+    Frame::new(10, 10, 630, 20, "Changesets that will be pushed (uncheck a repository to skip it):");
+
+    let scroll = fltk::group::Scroll::new(10, 40, 630, 410, "");
+    let mut pack = Pack::new(10, 40, 610, 0, "");
+    pack.set_spacing(4);
+
+    let mut checks = Vec::new();
+    for summary in summaries {
+        let count = summary.changesets.as_ref().map(|c| c.len()).unwrap_or(0);
+        let label = match &summary.changesets {
+            Ok(c) if c.is_empty() => format!("{} (nothing to push)", summary.path.display()),
+            Ok(c) if c.len() >= OUTGOING_LIMIT => {
+                format!("{} ({}+ changesets)", summary.path.display(), OUTGOING_LIMIT)
+            }
+            Ok(c) => format!("{} ({} changesets)", summary.path.display(), c.len()),
+            Err(_) => format!("{} (outgoing check failed)", summary.path.display()),
+        };
+        let check = fltk::button::CheckButton::default()
+            .with_size(0, 25)
+            .with_label(&label.replace('@', "@@"));
+        check.set_checked(count > 0);
+
+        let lines: Vec<String> = match &summary.changesets {
+            Ok(c) => c.iter().map(|l| format!("    {}", l)).collect(),
+            Err(e) => vec![format!("    Error: {}", e)],
+        };
+        for line in lines {
+            let mut frame = Frame::default()
+                .with_size(0, 20)
+                .with_label(&line.replace('@', "@@"));
+            frame.set_align(fltk::enums::Align::Left | fltk::enums::Align::Inside);
+            if summary.changesets.is_err() {
+                frame.set_label_color(Color::Red);
+            }
+        }
+        if summary.secret > 0 {
+            let mut frame = Frame::default()
+                .with_size(0, 20)
+                .with_label(&format!("    {} secret changesets stay local", summary.secret));
+            frame.set_align(fltk::enums::Align::Left | fltk::enums::Align::Inside);
+            frame.set_label_color(Color::DarkYellow);
+        }
+        checks.push((check, summary.path.clone()));
+    }
+    pack.end();
+    scroll.end();
+
+    let mut btn_cancel = Button::new(430, 460, 100, 30, "Cancel");
+    let mut btn_ok = Button::new(540, 460, 100, 30, "Push");
+
+    dialog.end();
+    dialog.make_modal(true);
+    dialog.show();
+
+    let mut d_clone = dialog.clone();
+    btn_cancel.set_callback(move |_| d_clone.hide());
+
+    let mut d_clone2 = dialog.clone();
+    btn_ok.set_callback(move |_| {
+        let paths: Vec<PathBuf> = checks
+            .iter()
+            .filter(|(c, _)| c.is_checked())
+            .map(|(_, p)| p.clone())
+            .collect();
+        let skipped = checks.len() - paths.len();
+        sender.send(Message::DoPush(paths, skipped));
+        d_clone2.hide();
+    });
+}
+
+/// Loads `hg status` for `repo` on a background thread and sends it back via `StatusFilesLoaded`.
+fn load_status_files(repo: Repository, sender: app::Sender<Message>) {
+    spawn_worker(move || {
+        let result = repo.get_status_files().map_err(|e| e.to_string());
+        sender.send(Message::StatusFilesLoaded(repo.path, result));
+    });
+}
+
+/// What the details panel shows beyond the fields already on `Repository`.
+#[derive(Clone)]
+struct RepoDetails {
+    default_remote: Option<String>,
+    message: Result<String, String>,
+    files: Result<Vec<(char, String)>, String>,
+}
+
+/// Loads the details panel contents for `repo` on a background thread and sends them back via `DetailsLoaded`.
+fn load_repo_details(repo: Repository, sender: app::Sender<Message>) {
+    spawn_worker(move || {
+        let default_remote = repo
+            .get_paths()
+            .ok()
+            .and_then(|paths| paths.into_iter().find(|(name, _)| name == "default"))
+            .map(|(_, url)| url);
+        let details = RepoDetails {
+            default_remote,
+            message: repo.get_commit_message().map_err(|e| e.to_string()),
+            files: repo.get_status_files().map_err(|e| e.to_string()),
+        };
+        sender.send(Message::DetailsLoaded(repo.path, details));
+    });
+}
+
+/// The header text and file list shown in the details panel for `repo`.
+fn details_text(repo: &Repository, details: &RepoDetails) -> (String, Vec<(char, String)>) {
+    let mut lines = vec![
+        repo.path.display().to_string(),
+        String::new(),
+        format!("Default remote: {}", details.default_remote.as_deref().unwrap_or("(none)")),
+        format!("Branch: {}", repo.current_branch.as_deref().unwrap_or("—")),
+        format!("Phase: {}", repo.phase.map(|p| p.to_string()).unwrap_or_default()),
+    ];
+    if !repo.last_date.is_empty() {
+        lines.push(format!("Last commit: {} by {}", repo.last_date, repo.last_author));
+    }
+    match &details.message {
+        Ok(message) if !message.is_empty() => {
+            lines.push(String::new());
+            lines.push(message.clone());
+        }
+        Ok(_) => {}
+        Err(e) => lines.push(format!("Error: {}", e)),
+    }
+    lines.push(String::new());
+    let files = match &details.files {
+        Ok(files) if files.is_empty() => {
+            lines.push("No changes".to_string());
+            Vec::new()
+        }
+        Ok(files) => {
+            lines.push(format!("{} changed files:", files.len()));
+            files.clone()
+        }
+        Err(e) => {
+            lines.push(format!("Error: {}", e));
+            Vec::new()
+        }
+    };
+    (lines.join("\n"), files)
+}
+
+/// Opens a read-only window with the full error of the last operation on
+/// `repo`, which the Status column cuts off.
+fn show_error_details(repo: &Repository) {
+    let text = repo.last_error.clone().unwrap_or_else(|| repo.last_status.clone());
+    let mut win = Window::default()
+        .with_size(600, 300)
+        .with_label(&format!("Error - {}", repo.path.display()));
+    win.set_border(true);
+    let mut flex = Flex::new(5, 5, 590, 290, "").column();
+    flex.set_spacing(5);
+
+    let mut buf = fltk::text::TextBuffer::default();
+    buf.set_text(&text);
+    let mut display = fltk::text::TextDisplay::default();
+    display.set_buffer(buf);
+    display.set_text_font(fltk::enums::Font::Courier);
+    display.set_text_size(12);
+    display.wrap_mode(fltk::text::WrapMode::AtBounds, 0);
+
+    let mut bottom = Flex::default().row();
+    Frame::default();
+    let mut btn_copy = Button::default().with_label("Copy");
+    let mut btn_close = Button::default().with_label("Close");
+    bottom.fixed(&btn_copy, 90);
+    bottom.fixed(&btn_close, 90);
+    bottom.end();
+    flex.fixed(&bottom, 30);
+
+    flex.end();
+    win.end();
+    win.resizable(&flex);
+    win.show();
+
+    btn_copy.set_callback(move |_| app::copy(&text));
+    let mut w = win.clone();
+    btn_close.set_callback(move |_| w.hide());
+}
+
+/// Opens the output log window on `buf`, which keeps growing while it is open.
+fn show_output_log(buf: &fltk::text::TextBuffer) -> (Window, fltk::text::TextDisplay) {
+    let mut win = Window::default().with_size(800, 500).with_label("Output Log");
+    win.set_border(true);
+    let mut flex = Flex::new(5, 5, 790, 490, "").column();
+    flex.set_spacing(5);
+
+    let mut display = fltk::text::TextDisplay::default();
+    display.set_buffer(buf.clone());
+    display.set_text_font(fltk::enums::Font::Courier);
+    display.set_text_size(12);
+
+    let mut bottom = Flex::default().row();
+    Frame::default();
+    let mut btn_copy = Button::default().with_label("Copy All");
+    let mut btn_clear = Button::default().with_label("Clear");
+    bottom.fixed(&btn_copy, 90);
+    bottom.fixed(&btn_clear, 90);
+    bottom.end();
+    flex.fixed(&bottom, 30);
+
+    flex.end();
+    win.end();
+    win.resizable(&flex);
+    win.show();
+
+    display.set_insert_position(buf.length());
+    display.show_insert_position();
+
+    let copy_buf = buf.clone();
+    btn_copy.set_callback(move |_| app::copy(&copy_buf.text()));
+    let mut clear_buf = buf.clone();
+    btn_clear.set_callback(move |_| clear_buf.set_text(""));
+    (win, display)
+}
+
+/// The open revision graph window; its text is filled in by `GraphLoaded`.
+struct GraphView {
+    win: Window,
+    buf: fltk::text::TextBuffer,
+    path: PathBuf,
+}
+
+/// Opens the read-only revision graph window for `path`. The limit spinner and
+/// Refresh button send `LoadGraph`; the caller sends the first one.
+fn show_graph_window(path: &std::path::Path, sender: app::Sender<Message>) -> GraphView {
+    let mut win = Window::default()
+        .with_size(700, 500)
+        .with_label(&format!("Graph - {}", path.display()));
+    win.set_border(true);
+    let mut flex = Flex::new(5, 5, 690, 490, "").column();
+    flex.set_spacing(5);
+
+    let mut top = Flex::default().row();
+    let label = Frame::default().with_label("Changesets:");
+    let mut spinner = fltk::misc::Spinner::default();
+    spinner.set_range(1.0, 10000.0);
+    spinner.set_step(10.0);
+    spinner.set_value(DEFAULT_GRAPH_LIMIT as f64);
+    Frame::default();
+    let mut btn_refresh = Button::default().with_label("Refresh");
+    top.fixed(&label, 90);
+    top.fixed(&spinner, 80);
+    top.fixed(&btn_refresh, 90);
+    top.end();
+    flex.fixed(&top, 30);
+
+    let buf = fltk::text::TextBuffer::default();
+    let mut display = fltk::text::TextDisplay::default();
+    display.set_buffer(buf.clone());
+    display.set_text_font(fltk::enums::Font::Courier);
+    display.set_text_size(12);
+
+    flex.end();
+    win.end();
+    win.resizable(&flex);
+    win.show();
+
+    let path = path.to_path_buf();
+    let path_c = path.clone();
+    btn_refresh.set_callback(move |_| {
+        sender.send(Message::LoadGraph(path_c.clone(), spinner.value() as usize));
+    });
+
+    GraphView { win, buf, path }
+}
+
+/// The open History window; its list is filled in by `HistoryLoaded`.
+struct HistoryView {
+    win: Window,
+    list: HoldBrowser,
+    path: PathBuf,
+    /// Revision of each line of `list`
+    revs: Rc<RefCell<Vec<String>>>,
+}
+
+/// Opens the read-only History window for `path`, listing the last
+/// `HISTORY_LIMIT` changesets. Double-clicking one offers to update to it.
+fn show_history_window(path: &std::path::Path, sender: app::Sender<Message>) -> HistoryView {
+    let mut win = Window::default()
+        .with_size(700, 400)
+        .with_label(&format!("History - {}", path.display()));
+    win.set_border(true);
+
+    let mut list = HoldBrowser::new(0, 0, 700, 400, "");
+    list.set_column_char('\t');
+    list.set_column_widths(&[60, 120, 120]);
+    list.add("Loading...");
+    win.end();
+    win.resizable(&list);
+    win.show();
+
+    let path = path.to_path_buf();
+    let revs: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
+    let path_c = path.clone();
+    let revs_c = revs.clone();
+    list.set_callback(move |list| {
+        if !app::event_clicks() {
+            return;
+        }
+        let Some(rev) = revs_c.borrow().get((list.value() - 1) as usize).cloned() else {
+            return;
+        };
+        let question = format!("Update {} to revision {}?", path_c.display(), rev);
+        if dialog::choice2(200, 200, &question, "Cancel", "Update", "") == Some(1) {
+            sender.send(Message::DoUpdateRev(vec![path_c.clone()], rev, false));
+        }
+    });
+
+    HistoryView { win, list, path, revs }
+}
+
+/// The open conflict resolution window; its list is filled in by `ConflictsLoaded`.
+struct ResolveView {
+    win: Window,
+    list: HoldBrowser,
+    path: PathBuf,
+    /// Unresolved files currently listed, in list order
+    files: Vec<String>,
+}
+
+/// Opens the window listing the unresolved files of `path`. Its buttons send
+/// `ResolveFile` for the selected file; the caller sends the first `LoadConflicts`.
+fn show_resolve_window(path: &Path, sender: app::Sender<Message>) -> ResolveView {
+    let mut win = Window::default()
+        .with_size(500, 400)
+        .with_label(&format!("Resolve Conflicts - {}", path.display()));
+    win.set_border(true);
+    let mut flex = Flex::new(10, 10, 480, 380, "").column();
+    flex.set_spacing(10);
+
+    let list = HoldBrowser::default();
+
+    let mut btn_row = Flex::default().row();
+    let mut btn_refresh = Button::default().with_label("Refresh");
+    Frame::default();
+    let mut btn_tool = Button::default().with_label("Launch Merge Tool");
+    let mut btn_mark = Button::default().with_label("Mark Resolved");
+    btn_row.fixed(&btn_refresh, 90);
+    btn_row.fixed(&btn_tool, 140);
+    btn_row.fixed(&btn_mark, 120);
+    btn_row.end();
+    flex.fixed(&btn_row, 30);
+
+    flex.end();
+    win.end();
+    win.resizable(&flex);
+    win.show();
+
+    let path = path.to_path_buf();
+    let path_c = path.clone();
+    btn_refresh.set_callback(move |_| sender.send(Message::LoadConflicts(path_c.clone())));
+    // The "@." format prefix is part of the line text, not of the file name
+    let selected_file = |list: &HoldBrowser| {
+        let line = list.value();
+        if line == 0 {
+            return None;
+        }
+        list.text(line)?.strip_prefix("@.").map(|f| f.to_string())
+    };
+    for (btn, mark) in [(&mut btn_mark, true), (&mut btn_tool, false)] {
+        let list = list.clone();
+        let path = path.clone();
+        btn.set_callback(move |_| match selected_file(&list) {
+            Some(file) => sender.send(Message::ResolveFile(path.clone(), file, mark)),
+            None => dialog::message(200, 200, "Select a file first"),
+        });
+    }
+
+    ResolveView { win, list, path, files: Vec::new() }
+}
+
+/// Opens a read-only window showing `diff`, with added/removed lines colored.
+fn show_diff_window(path: &std::path::Path, diff: &str) {
+    let mut text = diff.to_string();
+    if text.len() > MAX_DIFF_BYTES {
+        let mut cut = MAX_DIFF_BYTES;
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        text.truncate(cut);
+        text.push_str(&format!("\n\n[diff truncated at {} KB]\n", MAX_DIFF_BYTES / 1024));
+    }
+
+    // One style char per byte: A plain, B added, C removed, D hunk/file header, E binary/notice
+    let mut styles = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let style = if line.starts_with("+++")
+            || line.starts_with("---")
+            || line.starts_with("diff ")
+            || line.starts_with("@@")
+        {
+            'D'
+        } else if line.starts_with('+') {
+            'B'
+        } else if line.starts_with('-') {
+            'C'
+        } else if line.starts_with("Binary file") || line.starts_with("[diff truncated") {
+            'E'
+        } else {
+            'A'
+        };
+        styles.extend(std::iter::repeat_n(style, line.len()));
+    }
+
+    let entry = |color: Color| fltk::text::StyleTableEntry {
+        color,
+        font: fltk::enums::Font::Courier,
+        size: 12,
+    };
+    let style_table = vec![
+        entry(Color::Foreground),
+        entry(Color::from_rgb(0, 150, 0)),
+        entry(Color::from_rgb(200, 0, 0)),
+        entry(Color::from_rgb(0, 100, 200)),
+        entry(Color::from_rgb(180, 100, 0)),
+    ];
+
+    let mut win = Window::default()
+        .with_size(800, 600)
+        .with_label(&format!("Diff - {}", path.display()));
+    win.set_border(true);
+    let mut buf = fltk::text::TextBuffer::default();
+    buf.set_text(&text);
+    let mut style_buf = fltk::text::TextBuffer::default();
+    style_buf.set_text(&styles);
+    let mut display = fltk::text::TextDisplay::new(0, 0, 800, 600, "");
+    display.set_buffer(buf);
+    display.set_highlight_data(style_buf, style_table);
+    win.end();
+    win.resizable(&display);
+    win.show();
+}
+
+fn legend_text(prefs: &Preferences) -> String {
+    let mut text = String::from("Columns\n\n");
+    for col in visible_columns(prefs).into_iter().map(|i| &COLUMNS[i]) {
+        text.push_str(&format!("{}\n    {}\n", col.name, col.description));
+    }
+    text.push_str("\nStatus\n\n");
+    text.push_str("Ready\n    Refreshed, no operation run yet.\n");
+    text.push_str("<Operation>...\n    An operation is running on this repository.\n");
+    text.push_str("Error: <message>\n    The last operation failed; hg's message follows.\n");
+    text.push_str("[N subrepos]\n    The repository has subrepositories (.hgsub).\n");
+    if prefs.row_icons {
+        text.push_str("\nIcons\n\n");
+        text.push_str("↻ running   ⚠ failed or missing   ✎ uncommitted changes\n");
+        text.push_str("● public   ○ draft   ◌ secret\n");
+    }
+    if prefs.color_rows {
+        text.push_str("\nRow colors\n\n");
+        text.push_str("Red\n    The last operation failed.\n");
+        text.push_str("Blue\n    An operation is running, or was interrupted.\n");
+        text.push_str("Orange\n    Uncommitted changes.\n");
+        text.push_str("Grey\n    The repository folder was not found.\n");
+    }
+    text.push_str("\nPress Escape or click elsewhere to close.");
+    text
+}
+
+/// Opens the floating legend panel next to the header row of `parent`.
+fn show_legend(parent: &Window, prefs: &Preferences) -> Window {
+    let mut win = Window::new(parent.x() + parent.w() - 420, parent.y() + 54, 400, 360, "Legend");
+    win.set_border(true);
+    let mut buf = fltk::text::TextBuffer::default();
+    buf.set_text(&legend_text(prefs));
+    let mut display = fltk::text::TextDisplay::new(0, 0, 400, 360, "");
+    display.set_buffer(buf);
+    display.wrap_mode(fltk::text::WrapMode::AtBounds, 0);
+    win.end();
+
+    // Escape closes windows by default; also dismiss when focus moves elsewhere
+    win.handle(|w, ev| {
+        if ev == fltk::enums::Event::Unfocus {
+            w.hide();
+            return true;
+        }
+        false
+    });
+    win.show();
+    win
+}
+
+/// Status of a repository skipped or stopped by Cancel.
+const CANCELLED_STATUS: &str = "Cancelled";
+
+/// Cancellation flags of the running batches, with the repositories each works on.
+static RUNNING_BATCHES: Mutex<Vec<(Arc<AtomicBool>, Vec<PathBuf>)>> = Mutex::new(Vec::new());
+
+/// Registers a batch about to run on `repos`; its flag is set by `cancel_batches`.
+fn register_batch(repos: &[Repository]) -> Arc<AtomicBool> {
+    let flag = Arc::new(AtomicBool::new(false));
+    let paths = repos.iter().map(|r| r.path.clone()).collect();
+    RUNNING_BATCHES.lock().unwrap().push((flag.clone(), paths));
+    flag
+}
+
+fn unregister_batch(flag: &Arc<AtomicBool>) {
+    RUNNING_BATCHES.lock().unwrap().retain(|(f, _)| !Arc::ptr_eq(f, flag));
+}
+
+/// Whether a batch is running or queued, so a new one would have to wait.
+fn batches_pending() -> bool {
+    !RUNNING_BATCHES.lock().unwrap().is_empty()
+}
+
+/// Cancels every running batch: repositories not started yet are skipped and
+/// the hg commands already running are interrupted.
+fn cancel_batches() {
+    let batches = RUNNING_BATCHES.lock().unwrap();
+    for (flag, paths) in batches.iter() {
+        flag.store(true, Ordering::Relaxed);
+        repo::interrupt_commands(paths);
+    }
+}
+
+/// Global status once a batch started at `started` is done, e.g.
+/// "Ready: 40 repos in 3.2s", or the count of repositories completed and
+/// cancelled when it was cancelled.
+fn batch_done_status(total: usize, cancelled: usize, started: Instant) -> String {
+    let elapsed = format_elapsed(started.elapsed());
+    if cancelled == 0 {
+        format!("Ready: {} {} in {}", total, if total == 1 { "repo" } else { "repos" }, elapsed)
+    } else {
+        format!("Cancelled: {} completed, {} cancelled in {}", total - cancelled, cancelled, elapsed)
+    }
+}
+
+/// "3.2s" under a minute, "2m 05s" above.
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        format!("{:.1}s", elapsed.as_secs_f64())
+    } else {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    }
+}
+
+/// What a batch operation did in one repository.
+struct RepoOutcome {
+    status: String,
+    transaction: Transaction,
+}
+
+/// How a batch operation changed what `hg rollback` would undo.
+#[derive(Clone, Copy, PartialEq)]
+enum Transaction {
+    /// No transaction made, the last one is still the one to undo
+    Unchanged,
+    /// A transaction `hg rollback` undoes, e.g. "pull"
+    Made(&'static str),
+    /// Changes rolling back cannot reverse (a revert, a clean update...):
+    /// undoing now would roll back an older transaction instead
+    Cleared,
+}
+
+impl From<String> for RepoOutcome {
+    fn from(status: String) -> Self {
+        RepoOutcome { status, transaction: Transaction::Unchanged }
+    }
+}
+
+/// Runs `op` on one repository and returns its status.
+fn run_operation(op: Operation, r: &Repository) -> anyhow::Result<RepoOutcome> {
+    // A pull that found nothing makes no transaction, and rolling back then
+    // would undo an older one
+    let pulls = !matches!(
+        op,
+        Operation::CheckRemotes | Operation::UpdateLatest | Operation::UpdateLastPublic | Operation::Push
+    );
+    let before = if pulls { r.changeset_count().ok() } else { None };
+    let pulled = |r: &Repository| {
+        let after = r.changeset_count().ok();
+        match (before, after) {
+            (Some(before), Some(after)) if after > before => Transaction::Made("pull"),
+            _ => Transaction::Unchanged,
+        }
+    };
+    match op {
+        Operation::PullAll | Operation::PullCurrent | Operation::PullSubrepos => {
+            let out = match op {
+                Operation::PullAll => r.pull_all_branches()?,
+                Operation::PullCurrent => r.pull_current_branch()?,
+                _ => r.pull_with_subrepos()?,
+            };
+            Ok(RepoOutcome { status: summarize_pull_output(&out), transaction: pulled(r) })
+        }
+        Operation::PullAndUpdate => {
+            let status = match r.pull_and_update() {
+                Ok(_) => "Pulled and updated".to_string(),
+                Err(e) if matches!(e.downcast_ref(), Some(RepoError::UpdateCrossesBranches)) => {
+                    "Warning: pulled but not updated, crosses branches (pick a branch)".to_string()
+                }
+                Err(e) => return Err(e),
+            };
+            Ok(RepoOutcome { status, transaction: pulled(r) })
+        }
+        Operation::Sync => {
+            let res = r.sync(SYNC_MERGE_MESSAGE)?;
+            // The merge commit comes after the pull, so it is what gets undone
+            let transaction = if res.merged_heads > 0 { Transaction::Made("merge commit") } else { pulled(r) };
+            Ok(RepoOutcome { status: res.to_string(), transaction })
+        }
+        Operation::CheckRemotes => Ok(match r.count_incoming()? {
+            0 => "Up to date".to_string(),
+            n => incoming_status(n),
+        }
+        .into()),
+        Operation::UpdateLatest => r.update_to_latest().map(|_| "Success".to_string().into()),
+        Operation::UpdateLastPublic => r.update_to_last_public().map(|_| "Success".to_string().into()),
+        Operation::Push => r.push().map(|pushed| if pushed { "Pushed" } else { "Nothing to push" }.to_string().into()),
+    }
+}
+
+/// Refreshes `repos` in a batch. They show `WAITING_STATUS` while the batches
+/// queued before it run, and "Refreshing..." once their turn comes.
+fn spawn_refresh(repos: Vec<Repository>, sender: app::Sender<Message>) {
+    let status = if batches_pending() { WAITING_STATUS } else { "Refreshing..." };
+    for r in &repos {
+        sender.send(Message::SetStatus(r.path.clone(), status.to_string()));
+    }
+    spawn_batch(repos, sender, move |r| {
+        sender.send(Message::SetStatus(r.path.clone(), "Refreshing...".to_string()));
+        Ok("Ready".to_string())
+    });
+}
+
+/// `spawn_batch` for an operation Retry Failed can run again: it is recorded
+/// as each repository's `last_operation`.
+fn spawn_operation(op: Operation, repos: Vec<Repository>, sender: app::Sender<Message>) {
+    spawn_recorded_batch(repos, sender, Some(op), move |r| run_operation(op, r));
+}
+
+/// Runs `op` on every repository in parallel on a background thread.
+/// Each repo is refreshed afterwards and sent back via `RepoUpdated`, with the
+/// status returned by `op` (or the error) as its last status. Cancel skips the
+/// repos not started yet and interrupts the ones running, which end as `CANCELLED_STATUS`.
+fn spawn_batch<F>(repos: Vec<Repository>, sender: app::Sender<Message>, op: F)
+where
+    F: Fn(&Repository) -> anyhow::Result<String> + Send + Sync + 'static,
+{
+    spawn_recorded_batch(repos, sender, None, move |r| op(r).map(RepoOutcome::from));
+}
+
+/// `spawn_batch` for an operation returning a `RepoOutcome`, setting each
+/// repository's `last_operation` to `operation`. The transactions made, and
+/// whether one was cleared, are reported in the `BatchSummary`.
+fn spawn_recorded_batch<F>(repos: Vec<Repository>, sender: app::Sender<Message>, operation: Option<Operation>, op: F)
+where
+    F: Fn(&Repository) -> anyhow::Result<RepoOutcome> + Send + Sync + 'static,
+{
+    spawn_recorded_batch_then(repos, sender, operation, op, || {});
+}
+
+/// `spawn_recorded_batch`, running `then` on the batch thread once every
+/// repository is done, before `BatchComplete` is sent.
+fn spawn_recorded_batch_then<F, T>(
+    repos: Vec<Repository>,
+    sender: app::Sender<Message>,
+    operation: Option<Operation>,
+    op: F,
+    then: T,
+) where
+    F: Fn(&Repository) -> anyhow::Result<RepoOutcome> + Send + Sync + 'static,
+    T: FnOnce() + Send + 'static,
+{
+    if refuse_when_queue_full(&repos, sender) {
+        return;
+    }
+    let started = Instant::now();
+    let cancelled = register_batch(&repos);
+    sender.send(Message::OperationProgress(0, repos.len()));
+    queue_batch(move || {
+        let cancelled_count = AtomicUsize::new(0);
+        let failures = Mutex::new(Vec::new());
+        let transactions = Mutex::new(Vec::new());
+        let undo_cleared = AtomicBool::new(false);
+        repos.par_iter().for_each(|repo| {
+            let mut r = repo.clone();
+            r.last_operation = operation;
+            if cancelled.load(Ordering::Relaxed) {
+                cancelled_count.fetch_add(1, Ordering::Relaxed);
+                r.last_status = CANCELLED_STATUS.to_string();
+                sender.send(Message::RepoUpdated(r));
+                sender.send(Message::OperationProgress(1, 0));
+                return;
+            }
+            let op_started = Instant::now();
+            let res = op(&r);
+            // Only a command Cancel stopped counts as cancelled, not any failure after it
+            let killed = res.as_ref().is_err_and(|e| matches!(e.downcast_ref(), Some(RepoError::Interrupted)));
+            let failed = res.is_err() && !killed;
+            r.refresh();
+            let elapsed = op_started.elapsed();
+            r.last_op_duration = Some(elapsed);
+            // A missing repo keeps the status set by refresh rather than a spawn error
+            if !r.missing {
+                match res {
+                    _ if killed => {
+                        cancelled_count.fetch_add(1, Ordering::Relaxed);
+                        r.last_status = CANCELLED_STATUS.to_string();
+                    }
+                    Ok(outcome) => {
+                        match outcome.transaction {
+                            Transaction::Unchanged => {}
+                            Transaction::Made(kind) => transactions.lock().unwrap().push((r.path.clone(), kind)),
+                            Transaction::Cleared => undo_cleared.store(true, Ordering::Relaxed),
+                        }
+                        r.last_status = outcome.status;
+                        r.last_error = None;
+                    }
+                    Err(e) => {
+                        r.last_status = format!("Error: {}", e);
+                        r.last_error = Some(format!("{:#}", e));
+                    }
+                }
+            }
+            if failed {
+                failures.lock().unwrap().push((r.path.clone(), r.last_status.clone()));
+            }
+            let first_line = r.last_status.lines().next().unwrap_or_default();
+            sender.send(Message::LogLine(format!("{}: {} ({})\n", r.path.display(), first_line, format_elapsed(elapsed))));
+            sender.send(Message::RepoUpdated(r));
+            sender.send(Message::OperationProgress(1, 0));
+        });
+        unregister_batch(&cancelled);
+        then();
+        let skipped = cancelled_count.into_inner();
+        let mut failed = failures.into_inner().unwrap();
+        failed.sort();
+        let mut transactions = transactions.into_inner().unwrap();
+        transactions.sort();
+        sender.send(Message::BatchComplete(BatchSummary {
+            status: batch_done_status(repos.len(), skipped, started),
+            succeeded: repos.len() - failed.len() - skipped,
+            skipped,
+            failed,
+            transactions,
+            undo_cleared: undo_cleared.into_inner(),
+        }));
+    });
+}
+
+/// A change to a repository of the list, applied by `apply_repo_writes`.
+enum RepoWrite {
+    /// State from a worker, replacing the row's except for its alias
+    Replace(Box<Repository>),
+    Status(PathBuf, String),
+}
+
+/// How long writes are collected before the list is redrawn once for all of
+/// them, so rows finishing together don't flicker through each update.
+const REPO_WRITE_DELAY: f64 = 0.05;
+
+/// Adds `write` to `pending`, scheduling a `FlushRepoWrites` when it is the first.
+fn queue_repo_write(pending: &mut Vec<RepoWrite>, write: RepoWrite, sender: app::Sender<Message>) {
+    if pending.is_empty() {
+        app::add_timeout3(REPO_WRITE_DELAY, move |_| sender.send(Message::FlushRepoWrites));
+    }
+    pending.push(write);
+}
+
+/// Applies `writes` to `repos` in the order they were sent and returns the
+/// paths of the repositories replaced. Writes to repositories no longer in
+/// the list are dropped.
+fn apply_repo_writes(repos: &mut [Repository], writes: Vec<RepoWrite>, prefs: &Preferences) -> HashSet<PathBuf> {
+    let mut replaced = HashSet::new();
+    for write in writes {
+        match write {
+            RepoWrite::Replace(updated) => {
+                let Some(r) = repos.iter_mut().find(|r| r.path == updated.path) else {
+                    continue;
+                };
+                // Preserve status if not set in updated
+                let old_status = std::mem::take(&mut r.last_status);
+                // The alias is owned by the list, a worker may hold an older copy
+                let alias = r.alias.take();
+                *r = *updated;
+                r.alias = alias;
+                apply_repo_prefs(r, prefs);
+                if r.last_status.is_empty() {
+                    r.last_status = old_status;
+                }
+                replaced.insert(r.path.clone());
+            }
+            RepoWrite::Status(path, status) => {
+                if let Some(r) = repos.iter_mut().find(|r| r.path == path) {
+                    r.last_status = status;
+                }
+            }
+        }
+    }
+    replaced
+}
+
+/// "the pull in 3 repositories and the merge commit in 1 repository", from
+/// `BatchSummary::transactions`.
+fn describe_transactions(transactions: &[(PathBuf, &str)]) -> String {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for (_, kind) in transactions {
+        match counts.iter_mut().find(|(k, _)| k == kind) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((kind, 1)),
+        }
+    }
+    let parts: Vec<String> = counts
+        .iter()
+        .map(|(kind, n)| format!("the {} in {} {}", kind, n, if *n == 1 { "repository" } else { "repositories" }))
+        .collect();
+    parts.join(" and ")
+}
+
+/// Outcome of a `spawn_batch` batch.
+#[derive(Clone, Debug)]
+struct BatchSummary {
+    /// Global status, from `batch_done_status`
+    status: String,
+    succeeded: usize,
+    /// Skipped or stopped by Cancel
+    skipped: usize,
+    /// Path and status of each repository that failed, by path
+    failed: Vec<(PathBuf, String)>,
+    /// Repositories where the operation made a transaction, with what
+    /// `hg rollback` would undo there, by path
+    transactions: Vec<(PathBuf, &'static str)>,
+    /// Some repository changed in a way rolling back cannot reverse
+    undo_cleared: bool,
+}
+
+/// Opens the Batch Results window listing the failures of `summary`, with a
+/// button selecting their rows.
+fn show_batch_summary(summary: &BatchSummary, sender: app::Sender<Message>) {
+    let mut win = Window::default()
+        .with_size(600, 300)
+        .with_label("Batch Results");
+    win.set_border(true);
+    let mut flex = Flex::new(5, 5, 590, 290, "").column();
+    flex.set_spacing(5);
+
+    let mut counts = Frame::default().with_label(&format!(
+        "{} succeeded, {} failed, {} skipped",
+        summary.succeeded,
+        summary.failed.len(),
+        summary.skipped
+    ));
+    counts.set_align(fltk::enums::Align::Left | fltk::enums::Align::Inside);
+    flex.fixed(&counts, 20);
+
+    let mut list = HoldBrowser::default();
+    list.set_column_char('\t');
+    list.set_column_widths(&[250]);
+    for (path, status) in &summary.failed {
+        // "@." keeps the text from being parsed as format codes; Show Error Details has the rest
+        let first_line = status.lines().next().unwrap_or_default();
+        list.add(&format!("@.{}\t@.{}", path.display(), first_line));
+    }
+
+    let mut bottom = Flex::default().row();
+    Frame::default();
+    let mut btn_select = Button::default().with_label("Select Failed");
+    let mut btn_close = Button::default().with_label("Close");
+    bottom.fixed(&btn_select, 110);
+    bottom.fixed(&btn_close, 90);
+    bottom.end();
+    flex.fixed(&bottom, 30);
+
+    flex.end();
+    win.end();
+    win.resizable(&flex);
+    win.show();
+
+    let paths: Vec<PathBuf> = summary.failed.iter().map(|(path, _)| path.clone()).collect();
+    let mut w = win.clone();
+    btn_select.set_callback(move |_| {
+        sender.send(Message::SelectPaths(paths.clone()));
+        w.hide();
+    });
+    let mut w = win.clone();
+    btn_close.set_callback(move |_| w.hide());
+}
+
+/// Whether the desktop is in dark mode, `None` when it cannot be told. Asks the
+/// platform's own tools: the registry on Windows, `defaults` on macOS and the
+/// GNOME color scheme elsewhere.
+fn system_dark_mode() -> Option<bool> {
+    #[cfg(target_os = "windows")]
+    let output = {
+        let mut cmd = std::process::Command::new("reg");
+        cmd.args([
+            "query",
+            r"HKCU\Software\Microsoft\Windows\CurrentVersion\Themes\Personalize",
+            "/v",
+            "AppsUseLightTheme",
+        ]);
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        cmd.creation_flags(CREATE_NO_WINDOW);
+        cmd.output().ok()?
+    };
+    #[cfg(target_os = "macos")]
+    let output = std::process::Command::new("defaults")
+        .args(["read", "-g", "AppleInterfaceStyle"])
+        .output()
+        .ok()?;
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let output = std::process::Command::new("gsettings")
+        .args(["get", "org.gnome.desktop.interface", "color-scheme"])
+        .output()
+        .ok()?;
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    if cfg!(target_os = "windows") {
+        // "AppsUseLightTheme    REG_DWORD    0x0"
+        let value = text.lines().find(|l| l.contains("AppsUseLightTheme"))?.split_whitespace().last()?;
+        Some(value == "0x0")
+    } else if cfg!(target_os = "macos") {
+        // The key only exists in dark mode; defaults fails otherwise
+        Some(text.trim() == "Dark")
+    } else if output.status.success() {
+        Some(text.contains("dark"))
+    } else {
+        None
     }
+}
 
-    repos.sort_by(|a, b| {
-        let order = match state.column {
-            0 => a.path.cmp(&b.path),
-            1 => a.current_branch.cmp(&b.current_branch), // Branch
-            2 => a.revision.cmp(&b.revision),             // Rev
-            3 => a.modified.cmp(&b.modified),             // Mod
-            4 => a.commit_type.cmp(&b.commit_type),       // Phase
-            5 => a.last_status.cmp(&b.last_status),       // Status
-            _ => std::cmp::Ordering::Equal,
-        };
+/// Opens `path` with the application the desktop associates with it.
+fn open_in_default_app(path: &Path) -> std::io::Result<()> {
+    #[cfg(target_os = "windows")]
+    let mut cmd = {
+        let mut cmd = std::process::Command::new("cmd");
+        cmd.args(["/C", "start", ""]).arg(path);
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        cmd.creation_flags(CREATE_NO_WINDOW);
+        cmd
+    };
+    #[cfg(target_os = "macos")]
+    let mut cmd = {
+        let mut cmd = std::process::Command::new("open");
+        cmd.arg(path);
+        cmd
+    };
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let mut cmd = {
+        let mut cmd = std::process::Command::new("xdg-open");
+        cmd.arg(path);
+        cmd
+    };
+    cmd.spawn().map(|_| ())
+}
+
+/// Opens `path` in VS Code with `command`. On Windows `code` is a `.cmd` shim that
+/// is not found without its extension, so a failed start is retried as `<command>.cmd`.
+/// The shim is started directly rather than through `cmd /C`, which would let
+/// characters such as `&` in the path run as commands.
+fn open_in_vscode(command: &str, path: &Path) -> std::io::Result<()> {
+    let mut cmd = std::process::Command::new(command);
+    cmd.arg(path);
+    match cmd.spawn() {
+        Ok(_) => Ok(()),
+        #[cfg(target_os = "windows")]
+        Err(_) => {
+            let mut cmd = std::process::Command::new(format!("{}.cmd", command));
+            cmd.arg(path);
+            const CREATE_NO_WINDOW: u32 = 0x08000000;
+            cmd.creation_flags(CREATE_NO_WINDOW);
+            cmd.spawn().map(|_| ())
+        }
+        #[cfg(not(target_os = "windows"))]
+        Err(e) => Err(e),
+    }
+}
+
+/// Turns the text of a file drop into the directories it names. Depending on the
+/// platform this is newline-separated paths or `file://` URIs; dropped files are skipped.
+fn parse_dropped_paths(text: &str) -> Vec<PathBuf> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.strip_prefix("file://") {
+            Some(uri) => {
+                let decoded = percent_decode(uri);
+                // "file:///C:/dir" leaves "/C:/dir" on Windows
+                match decoded.strip_prefix('/') {
+                    Some(rest) if rest.get(1..2) == Some(":") => PathBuf::from(rest),
+                    _ => PathBuf::from(decoded),
+                }
+            }
+            None => PathBuf::from(line),
+        })
+        .filter(|p| p.is_dir())
+        .collect()
+}
+
+/// Decodes `%XX` escapes in a URI path.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = text.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(byte);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Whether a repository between `path` and the scan `root` lists `path` in its
+/// `.hgsub`. Subrepositories are handled through their parent, not as list entries.
+fn is_listed_subrepo(path: &Path, root: &Path) -> bool {
+    path.ancestors()
+        .skip(1)
+        .take_while(|a| a.starts_with(root))
+        .filter(|a| a.join(".hg").is_dir())
+        .any(|a| {
+            Repository::new(a.to_path_buf())
+                .read_hgsub()
+                .iter()
+                .any(|sub| a.join(sub) == path)
+        })
+}
+
+/// Lists the profiles of `config` in `choice`, sorted, with `active` selected.
+fn fill_profile_choice(choice: &mut fltk::menu::Choice, config: &AppConfig, active: &str) {
+    let mut names: Vec<&str> = config.profiles.keys().map(|n| n.as_str()).collect();
+    if !names.contains(&active) {
+        names.push(active);
+    }
+    names.sort_unstable();
+    choice.clear();
+    for name in &names {
+        choice.add_choice(name);
+    }
+    if let Some(i) = names.iter().position(|n| *n == active) {
+        choice.set_value(i as i32);
+    }
+}
+
+/// Copies the preferences that change what `Repository::refresh` runs onto `repo`.
+fn apply_repo_prefs(repo: &mut Repository, prefs: &Preferences) {
+    repo.recurse_subrepos = prefs.recurse_subrepos;
+    repo.check_heads = prefs.check_heads;
+}
+
+/// Walks `dirs` for `.hg` folders. Each repository is refreshed on the rayon pool
+/// as soon as it is found and sent as `RepoDiscovered`, so rows appear while the
+/// walk is still running; `ScanComplete` follows once everything is analyzed.
+fn scan_repositories(dirs: Vec<PathBuf>, prefs: Preferences, sender: app::Sender<Message>) {
+    sender.send(Message::SetGlobalStatus("Walking directories...".into()));
+    let mut found = 0;
+
+    rayon::scope(|scope| {
+        for dir in dirs {
+            sender.send(Message::SetGlobalStatus(format!(
+                "Walking {}...",
+                dir.display()
+            )));
+            walk_repositories(&dir, |path| {
+                found += 1;
+                let prefs = &prefs;
+                scope.spawn(move |_| {
+                    let mut r = Repository::new(path);
+                    apply_repo_prefs(&mut r, prefs);
+                    r.refresh();
+                    sender.send(Message::RepoDiscovered(r));
+                });
+            });
+        }
+    });
+
+    sender.send(Message::ScanComplete(found));
+}
+
+/// Calls `found` with each repository under `dir`, as it is walked.
+fn walk_repositories(dir: &Path, mut found: impl FnMut(PathBuf)) {
+    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_dir()
+            && entry.file_name() == ".hg"
+            && let Some(parent) = entry.path().parent()
+            && !is_listed_subrepo(parent, dir)
+        {
+            found(parent.to_path_buf());
+        }
+    }
+}
+
+/// (Re)creates the header buttons for the visible columns, showing the sort
+/// arrow on the sorted ones (smaller for secondary keys), followed by the
+/// legend "?" button.
+fn build_header(header: &mut Group, prefs: &Preferences, state: &SortState, sender: app::Sender<Message>) {
+    header.clear();
+    header.begin();
+    let mut x_off = header.x();
+    let widths = layout_column_widths(prefs, header.w());
+    for (i, width) in visible_columns(prefs).into_iter().zip(widths) {
+        let col = &COLUMNS[i];
+        let mut label = col.name.to_string();
+        if let Some(pos) = state.keys.iter().position(|&(c, _)| c == i) {
+            label.push_str(match (pos, state.keys[pos].1) {
+                (0, SortOrder::Ascending) => " ▲",
+                (0, SortOrder::Descending) => " ▼",
+                (_, SortOrder::Ascending) => " ▴",
+                (_, SortOrder::Descending) => " ▾",
+            });
+        }
+        let mut btn = Button::new(x_off, header.y(), width, 24, None);
+        btn.set_label(&label);
+        btn.set_frame(FrameType::ThinUpBox);
+        btn.set_label_size(12);
+        btn.set_callback(move |_| {
+            let shift = app::event_state().contains(fltk::enums::EventState::Shift);
+            sender.send(Message::Sort(i, shift));
+        });
+        x_off += width;
+    }
+    let mut btn_legend = Button::new(x_off, header.y(), LEGEND_BUTTON_WIDTH, 24, "?");
+    btn_legend.set_frame(FrameType::ThinUpBox);
+    btn_legend.set_label_size(12);
+    btn_legend.set_tooltip("Show legend");
+    btn_legend.emit(sender, Message::ToggleLegend);
+    header.end();
+    header.redraw();
+}
+
+fn cell_text(id: ColumnId, repo: &Repository, prefs: &Preferences) -> String {
+    match id {
+        ColumnId::Path => {
+            if prefs.show_full_path {
+                repo.path.display().to_string()
+            } else if let Some(alias) = &repo.alias {
+                alias.clone()
+            } else {
+                repo.path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string()
+            }
+        }
+        ColumnId::Branch => match (&repo.current_branch, &repo.branch_error) {
+            (Some(branch), _) => branch.clone(),
+            // Unknown branch: the reason is shown in the tooltip when the row is focused
+            (None, Some(_)) => "—".to_string(),
+            (None, None) => String::new(),
+        },
+        ColumnId::Rev => {
+            if prefs.show_hash {
+                repo.node_short.clone()
+            } else if repo.node_short.is_empty() {
+                repo.revision.clone()
+            } else {
+                format!("{}:{}", repo.revision.trim_end_matches('+'), repo.node_short)
+            }
+        }
+        ColumnId::Mod => match (repo.needs_merge, repo.modified) {
+            (true, true) => "Yes, Merge!",
+            (true, false) => "Merge!",
+            (false, true) => "Yes",
+            (false, false) => "No",
+        }
+        .to_string(),
+        ColumnId::Phase => repo.phase.map(|p| p.to_string()).unwrap_or_default(),
+        ColumnId::Status => {
+            let mut text = String::new();
+            if let Some(op) = repo.interrupted {
+                text.push_str(&format!("[{}] ", op.to_string().to_uppercase()));
+            }
+            if repo.applied_patches > 0 {
+                text.push_str(&format!("[{} patches applied] ", repo.applied_patches));
+            }
+            if repo.subrepo_count > 0 {
+                text.push_str(&format!("[{} subrepos] ", repo.subrepo_count));
+            }
+            text.push_str(&repo.last_status);
+            text
+        }
+        ColumnId::Tag => repo.latest_tag.clone(),
+        ColumnId::Author => repo.last_author.clone(),
+        ColumnId::Date => repo.last_commit_time.and_then(relative_date).unwrap_or_default(),
+        ColumnId::Hash => repo.node_short.clone(),
+        ColumnId::Duration => repo.last_op_duration.map(format_elapsed).unwrap_or_default(),
+    }
+}
 
-        if state.order == SortOrder::Descending {
-            order.reverse()
+/// Renders a commit time in Unix seconds relative to now, e.g. "2 days ago".
+fn relative_date(timestamp: i64) -> Option<String> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs() as i64;
+    let ago = |n: i64, unit: &str| {
+        if n == 1 {
+            format!("1 {} ago", unit)
         } else {
-            order
+            format!("{} {}s ago", n, unit)
+        }
+    };
+    let secs = (now - timestamp).max(0);
+    Some(match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => ago(secs / 60, "minute"),
+        3600..86400 => ago(secs / 3600, "hour"),
+        86400..172800 => "yesterday".to_string(),
+        172800..2592000 => ago(secs / 86400, "day"),
+        2592000..31536000 => ago(secs / 2592000, "month"),
+        _ => ago(secs / 31536000, "year"),
+    })
+}
+
+/// Tooltip for the focused row: details that have no column of their own.
+fn repo_tooltip(repo: &Repository) -> String {
+    let mut lines = Vec::new();
+    if let Some(e) = &repo.branch_error {
+        lines.push(format!("Branch unknown: {}", e));
+    }
+    if !repo.tags.is_empty() {
+        lines.push(format!("Tags: {}", repo.tags.join(", ")));
+    }
+    if !repo.last_date.is_empty() {
+        lines.push(format!("Last commit: {} by {}", repo.last_date, repo.last_author));
+    }
+    if repo.subrepo_count > 0 {
+        lines.push(format!("{} subrepositories", repo.subrepo_count));
+    }
+    if repo.applied_patches > 0 {
+        lines.push(format!("{} mq patches applied", repo.applied_patches));
+    }
+    lines.join("\n")
+}
+
+/// One line of the repository list.
+enum Row {
+    /// Non-selectable header naming the parent directory of the repos below it
+    Group(PathBuf),
+    /// Index into the repository list
+    Repo(usize),
+}
+
+/// Whether `repo` passes the View menu filter. With "Show Modified Only" the
+/// list keeps the repos with uncommitted changes or a failed operation.
+fn shown_in_list(repo: &Repository, prefs: &Preferences) -> bool {
+    !prefs.modified_only || repo.modified || repo.last_status.starts_with("Error")
+}
+
+/// Lays out the browser lines for `repos`. Both `update_browser` and the
+/// line-to-repo mapping go through this so they always agree.
+fn layout_rows(repos: &[Repository], prefs: &Preferences) -> Vec<Row> {
+    let mut order: Vec<usize> = (0..repos.len()).filter(|&i| shown_in_list(&repos[i], prefs)).collect();
+    if !prefs.group_by_parent {
+        return order.into_iter().map(Row::Repo).collect();
+    }
+
+    // Stable sort keeps the current column sort within each group
+    order.sort_by_key(|&i| repos[i].path.parent().map(|p| p.to_path_buf()));
+
+    let mut rows = Vec::new();
+    let mut current: Option<PathBuf> = None;
+    for i in order {
+        let parent = repos[i].path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        if current.as_ref() != Some(&parent) {
+            rows.push(Row::Group(parent.clone()));
+            current = Some(parent);
+        }
+        rows.push(Row::Repo(i));
+    }
+    rows
+}
+
+/// Refills the list from `repos`, then selects the rows of `selection` (paths
+/// captured with `selected_paths` before the change) and restores the scroll
+/// position, so live updates do not disturb the user. The window title is
+/// updated with the counts from `summarize`.
+fn update_browser(browser: &mut MultiBrowser, repos: &[Repository], prefs: &Preferences, selection: &HashSet<PathBuf>) {
+    let (position, hposition) = (browser.position(), browser.hposition());
+    let columns = visible_columns(prefs);
+    browser.set_column_widths(&layout_column_widths(prefs, browser.w()));
+    browser.clear();
+
+    for row in layout_rows(repos, prefs) {
+        match row {
+            Row::Group(parent) => {
+                // "@b" bolds the header, "@." stops format parsing for the path itself
+                browser.add(&format!("@b@.{}", parent.display()));
+            }
+            Row::Repo(idx) => {
+                // Missing repos are greyed out; "@." keeps the text itself from being parsed
+                let prefix = if repos[idx].missing {
+                    format!("@C{}@.", Color::Inactive.bits())
+                } else if let Some(color) = row_color(&repos[idx], prefs) {
+                    format!("@C{}@.", color.bits())
+                } else {
+                    String::new()
+                };
+                let icons = if prefs.row_icons { row_icons(&repos[idx]) } else { String::new() };
+                // The icons go in the first cell, after the format codes; Copy and
+                // type-ahead read the repository, not this text
+                let line = columns
+                    .iter()
+                    .enumerate()
+                    .map(|(n, &i)| {
+                        let icons = if n == 0 { icons.as_str() } else { "" };
+                        format!("{}{}{}", prefix, icons, cell_text(COLUMNS[i].id, &repos[idx], prefs))
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\t");
+                browser.add(&line);
+            }
+        }
+    }
+
+    for line in lines_for_paths(repos, prefs, selection) {
+        browser.select(line);
+    }
+    browser.set_position(position);
+    browser.set_hposition(hposition);
+
+    if let Some(mut wind) = browser.top_window() {
+        let title = summarize(repos);
+        if wind.label() != title {
+            wind.set_label(&title);
+        }
+    }
+    update_summary_bar(browser, repos);
+}
+
+/// Sets the counts at the right of the status bar: repositories, modified,
+/// running and selected, then the errored ones on a button selecting them.
+/// Called by `update_browser` and on selection changes.
+fn update_summary_bar(browser: &MultiBrowser, repos: &[Repository]) {
+    let count = |f: fn(&Repository) -> bool| repos.iter().filter(|r| f(r)).count();
+    if let Some(mut counts) = app::widget_from_id::<Frame>(SUMMARY_COUNTS_ID) {
+        let label = format!(
+            "{} repos, {} modified, {} running, {} selected",
+            repos.len(),
+            count(|r| r.modified),
+            count(|r| r.last_status.ends_with("...")),
+            browser.selected_items().len()
+        );
+        if counts.label() != label {
+            counts.set_label(&label);
+        }
+    }
+    if let Some(mut errors) = app::widget_from_id::<Button>(SUMMARY_ERRORS_ID) {
+        let errored = count(|r| r.last_status.starts_with("Error"));
+        let label = format!("{} errored", errored);
+        if errors.label() != label {
+            errors.set_label(&label);
+            errors.set_label_color(if errored > 0 { Color::Red } else { Color::Gray0 });
+            if errored > 0 {
+                errors.activate();
+            } else {
+                errors.deactivate();
+            }
         }
+    }
+}
+
+/// Window title with counts over the whole list, rows hidden by a view filter
+/// included: "ManaHg — 42 repos, 5 modified, 2 errors".
+fn summarize(repos: &[Repository]) -> String {
+    if repos.is_empty() {
+        return "ManaHg".to_string();
+    }
+    let mut parts = vec![format!("{} repo{}", repos.len(), if repos.len() == 1 { "" } else { "s" })];
+    let modified = repos.iter().filter(|r| r.modified).count();
+    if modified > 0 {
+        parts.push(format!("{} modified", modified));
+    }
+    let errors = repos.iter().filter(|r| r.last_status.starts_with("Error")).count();
+    if errors > 0 {
+        parts.push(format!("{} error{}", errors, if errors == 1 { "" } else { "s" }));
+    }
+    format!("ManaHg — {}", parts.join(", "))
+}
+
+/// Glyphs shown before the path: the state (running, failed or modified)
+/// then the phase, each a figure space when there is nothing to show so the
+/// paths stay roughly aligned. Drawn as text in the row's color, so they
+/// follow the theme.
+fn row_icons(repo: &Repository) -> String {
+    let state = if repo.last_status.ends_with("...") {
+        '↻'
+    } else if repo.last_status.starts_with("Error") || repo.missing {
+        '⚠'
+    } else if repo.modified {
+        '✎'
+    } else {
+        '\u{2007}'
+    };
+    let phase = match repo.phase {
+        Some(Phase::Public) => '●',
+        Some(Phase::Draft) => '○',
+        Some(Phase::Secret) => '◌',
+        None => '\u{2007}',
+    };
+    format!("{}{} ", state, phase)
+}
+
+/// Text color for a repo's row, `None` for the theme's default. Red for a
+/// failed operation, blue for one running or interrupted, orange for
+/// uncommitted changes; lighter shades on dark backgrounds.
+fn row_color(repo: &Repository, prefs: &Preferences) -> Option<Color> {
+    if !prefs.color_rows {
+        return None;
+    }
+    let (r, g, b) = Color::Background2.to_rgb();
+    let dark = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000 < 128;
+    let rgb = if repo.last_status.starts_with("Error") {
+        if dark { (255, 120, 120) } else { (200, 0, 0) }
+    } else if repo.last_status.ends_with("...") || repo.interrupted.is_some() {
+        if dark { (120, 170, 255) } else { (0, 80, 200) }
+    } else if repo.modified {
+        if dark { (255, 180, 80) } else { (200, 110, 0) }
+    } else {
+        return None;
+    };
+    Some(Color::from_rgb(rgb.0, rgb.1, rgb.2))
+}
+
+/// Local revision number of a Rev value such as "123" or "123+" (uncommitted
+/// changes); `None` for unknown revisions ("?", empty).
+fn rev_number(revision: &str) -> Option<u64> {
+    revision.trim_end_matches('+').parse().ok()
+}
+
+/// Status text written by Check Remotes for a repo that is behind its remote.
+fn incoming_status(count: usize) -> String {
+    format!("{} incoming", count)
+}
+
+/// Reads back the count from a status written by `incoming_status`.
+fn parse_incoming(status: &str) -> Option<usize> {
+    status.strip_suffix(" incoming")?.parse().ok()
+}
+
+/// Status after a pull, read from hg's output: "Up to date", or "Pulled N
+/// changesets" summed over the "added N changesets with ..." lines (there is
+/// one per repository pulled, subrepositories included).
+fn summarize_pull_output(out: &str) -> String {
+    let added: Vec<usize> = out
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("added ")?.split_whitespace().next()?.parse().ok())
+        .collect();
+    let count: usize = added.iter().sum();
+    if added.is_empty() && !out.contains("no changes found") {
+        // Not the output of a plain hg pull; nothing to report but success
+        return "Success".to_string();
+    }
+    match count {
+        0 => "Up to date".to_string(),
+        1 => "Pulled 1 changeset".to_string(),
+        n => format!("Pulled {} changesets", n),
+    }
+}
+
+/// Sort key for the Status column: repos behind their remote sort together,
+/// ordered by how many changesets they are missing.
+fn status_sort_key(status: &str) -> (bool, usize, &str) {
+    match parse_incoming(status) {
+        Some(count) => (false, count, ""),
+        None => (true, 0, status),
+    }
+}
+
+/// Stable, and ends with the path, so rows that tie on every key keep the same
+/// order between refreshes.
+fn sort_repos(repos: &mut Vec<Repository>, state: &SortState, prefs: &Preferences) {
+    let keys: Vec<(ColumnId, SortOrder)> = state
+        .keys
+        .iter()
+        .filter_map(|&(c, order)| COLUMNS.get(c).map(|col| (col.id, order)))
+        .collect();
+    repos.sort_by(|a, b| {
+        keys.iter()
+            .map(|&(id, order)| compare_column(id, order, a, b, prefs))
+            .find(|o| o.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.path.cmp(&b.path))
     });
 }
 
-fn get_selected_repos(browser: &MultiBrowser, repos: &[Repository]) -> Vec<Repository> {
+fn compare_column(id: ColumnId, order: SortOrder, a: &Repository, b: &Repository, prefs: &Preferences) -> std::cmp::Ordering {
+    // The Rev column shows hashes, which only sort as text
+    let id = if id == ColumnId::Rev && prefs.show_hash { ColumnId::Hash } else { id };
+    // Unknown revisions stay at the end in both directions
+    if id == ColumnId::Rev {
+        let unknown = rev_number(&a.revision).is_none().cmp(&rev_number(&b.revision).is_none());
+        if unknown.is_ne() {
+            return unknown;
+        }
+    }
+    let ordering = match id {
+        ColumnId::Path => a.path.cmp(&b.path),
+        ColumnId::Branch => a.current_branch.cmp(&b.current_branch),
+        ColumnId::Rev => rev_number(&a.revision).cmp(&rev_number(&b.revision)),
+        ColumnId::Mod => (a.needs_merge, a.modified).cmp(&(b.needs_merge, b.modified)),
+        ColumnId::Phase => a.phase.cmp(&b.phase),
+        ColumnId::Status => status_sort_key(&a.last_status).cmp(&status_sort_key(&b.last_status)),
+        ColumnId::Tag => a.latest_tag.cmp(&b.latest_tag),
+        ColumnId::Author => a.last_author.cmp(&b.last_author),
+        // Repos without a commit time sort before the oldest ones
+        ColumnId::Date => a.last_commit_time.cmp(&b.last_commit_time),
+        ColumnId::Hash => a.node_short.cmp(&b.node_short),
+        ColumnId::Duration => a.last_op_duration.cmp(&b.last_op_duration),
+    };
+
+    if order == SortOrder::Descending {
+        ordering.reverse()
+    } else {
+        ordering
+    }
+}
+
+/// Expands the placeholders of a commit message shared by several repositories:
+/// `{branch}` (current branch) and `{name}` (alias, or folder name).
+fn expand_commit_template(template: &str, repo: &Repository) -> anyhow::Result<String> {
+    let mut message = template.to_string();
+    if message.contains("{branch}") {
+        let branch = repo.current_branch.as_deref().ok_or(RepoError::BranchUnknown)?;
+        message = message.replace("{branch}", branch);
+    }
+    if message.contains("{name}") {
+        let name = match &repo.alias {
+            Some(alias) => alias.clone(),
+            None => repo.path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        };
+        message = message.replace("{name}", &name);
+    }
+    Ok(message)
+}
+
+/// The visible columns of `repos` as a GitHub-flavored Markdown table.
+fn markdown_table(repos: &[Repository], prefs: &Preferences) -> String {
+    let columns = visible_columns(prefs);
+    let row = |cells: Vec<String>| format!("| {} |", cells.join(" | "));
+    // Backslashes first, so the ones added for pipes are not doubled; a line break would end the row
+    let escape = |text: String| {
+        text.replace('\\', "\\\\")
+            .replace('|', "\\|")
+            .replace("\r\n", "<br>")
+            .replace(['\r', '\n'], "<br>")
+    };
+
+    let mut lines = vec![
+        row(columns.iter().map(|&i| COLUMNS[i].name.to_string()).collect()),
+        row(columns.iter().map(|_| "---".to_string()).collect()),
+    ];
+    for repo in repos {
+        lines.push(row(columns
+            .iter()
+            .map(|&i| escape(cell_text(COLUMNS[i].id, repo, prefs)))
+            .collect()));
+    }
+    lines.join("\n")
+}
+
+/// Clipboard text for one value per selected repository: the bare value for a
+/// single repository, otherwise "path<TAB>value" lines that paste into a spreadsheet.
+fn copy_text(repos: &[Repository], values: &[String]) -> String {
+    if let [value] = values {
+        return value.clone();
+    }
+    repos
+        .iter()
+        .zip(values)
+        .map(|(r, v)| format!("{}\t{}", r.path.display(), v))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn get_selected_repos(browser: &MultiBrowser, repos: &[Repository], prefs: &Preferences) -> Vec<Repository> {
+    let rows = layout_rows(repos, prefs);
     let mut selected = Vec::new();
     let lines = browser.selected_items();
     for idx in lines {
-        if idx > 0 {
-            // 1-based index but no header anymore so item 1 is index 0
-            let repo_idx = (idx - 1) as usize;
-            if repo_idx < repos.len() {
-                selected.push(repos[repo_idx].clone());
-            }
+        if let Some(repo_idx) = repo_index_for_line(&rows, idx) {
+            selected.push(repos[repo_idx].clone());
         }
     }
     selected
 }
+
+/// Maps a 1-based browser line to its index in the repository list, skipping group headers.
+fn repo_index_for_line(rows: &[Row], line: i32) -> Option<usize> {
+    if line < 1 {
+        return None;
+    }
+    match rows.get((line - 1) as usize) {
+        Some(Row::Repo(idx)) => Some(*idx),
+        _ => None,
+    }
+}
+
+/// Paths of the selected repositories, captured before a change that rebuilds or reorders the list.
+fn selected_paths(browser: &MultiBrowser, repos: &[Repository], prefs: &Preferences) -> HashSet<PathBuf> {
+    let rows = layout_rows(repos, prefs);
+    browser
+        .selected_items()
+        .into_iter()
+        .filter_map(|line| repo_index_for_line(&rows, line))
+        .map(|idx| repos[idx].path.clone())
+        .collect()
+}
+
+/// Browser lines (1-based) that show one of `paths` once the list is laid out for
+/// `repos`. Matching by path keeps the selection across sorting and grouping changes.
+fn lines_for_paths(repos: &[Repository], prefs: &Preferences, paths: &HashSet<PathBuf>) -> Vec<i32> {
+    layout_rows(repos, prefs)
+        .iter()
+        .enumerate()
+        .filter_map(|(i, row)| match row {
+            Row::Repo(idx) if paths.contains(&repos[*idx].path) => Some(i as i32 + 1),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether `text` contains `pattern`, or with `*` or `?` in it, matches it as a
+/// whole (`*` any run of characters, `?` any one character).
+fn matches_pattern(pattern: &str, text: &str, case_sensitive: bool) -> bool {
+    let (pattern, text) = if case_sensitive {
+        (pattern.to_string(), text.to_string())
+    } else {
+        (pattern.to_lowercase(), text.to_lowercase())
+    };
+    if !pattern.contains(['*', '?']) {
+        return text.contains(&pattern);
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_match(&pattern, &text)
+}
+
+/// Matches `text` against a whole `*`/`?` pattern. On a mismatch only the last `*`
+/// is tried again one character further, which keeps it O(n·m) whatever the pattern.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen and of the text it is currently matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn repos_by_path(repos: &[Repository], paths: &[PathBuf]) -> Vec<Repository> {
+    repos
+        .iter()
+        .filter(|r| paths.contains(&r.path))
+        .cloned()
+        .collect()
+}
+
+/// Resolves the repositories a batch operation runs on. Every batch handler goes
+/// through here (via `confirm_targets`) so the targeting rules live in one place.
+fn resolve_targets(
+    scope: Scope,
+    browser: &MultiBrowser,
+    repos: &[Repository],
+    prefs: &Preferences,
+) -> Vec<Repository> {
+    targets_for_lines(scope, &browser.selected_items(), browser.size(), repos, prefs)
+}
+
+/// `resolve_targets` for a list of `line_count` lines with `selected` (1-based) selected.
+/// Missing repositories would only fail, so they are never targets.
+fn targets_for_lines(
+    scope: Scope,
+    selected: &[i32],
+    line_count: i32,
+    repos: &[Repository],
+    prefs: &Preferences,
+) -> Vec<Repository> {
+    let rows = layout_rows(repos, prefs);
+    let lines: Vec<i32> = match scope {
+        Scope::Selected => selected.to_vec(),
+        Scope::View => (1..=line_count).collect(),
+    };
+    lines
+        .into_iter()
+        .filter_map(|line| repo_index_for_line(&rows, line))
+        .filter(|&idx| !repos[idx].missing)
+        .map(|idx| repos[idx].clone())
+        .collect()
+}
+
+/// Warns before `action` runs on repositories with mq patches applied, offering
+/// to leave them out. Returns `None` when cancelled or when nothing is left.
+fn confirm_applied_patches(action: &str, targets: Vec<Repository>) -> Option<Vec<Repository>> {
+    let patched: Vec<&Repository> = targets.iter().filter(|r| r.applied_patches > 0).collect();
+    if patched.is_empty() {
+        return Some(targets);
+    }
+    let mut question = format!(
+        "{} of {} repositories have mq patches applied:\n\n",
+        patched.len(),
+        targets.len()
+    );
+    for r in &patched {
+        question.push_str(&format!("{} ({} patches)\n", r.path.display(), r.applied_patches));
+    }
+    question.push_str(&format!("\n{} may leave their patch queues inconsistent.", action));
+    match dialog::choice2(200, 200, &question, "Cancel", "Skip Them", "Run Anyway") {
+        Some(1) => {
+            let rest: Vec<Repository> = targets.into_iter().filter(|r| r.applied_patches == 0).collect();
+            (!rest.is_empty()).then_some(rest)
+        }
+        Some(2) => Some(targets),
+        _ => None,
+    }
+}
+
+/// Shows which repositories `action` is about to run on, letting the user choose
+/// between the selection and everything in view. Returns `None` when cancelled and
+/// an empty list (without asking) when there is nothing to run on.
+fn confirm_targets(
+    action: &str,
+    browser: &MultiBrowser,
+    repos: &[Repository],
+    prefs: &Preferences,
+) -> Option<Vec<Repository>> {
+    let selected = resolve_targets(Scope::Selected, browser, repos, prefs);
+    let in_view = resolve_targets(Scope::View, browser, repos, prefs);
+    if selected.is_empty() && in_view.is_empty() {
+        return Some(Vec::new());
+    }
+    // With nothing selected the "selected" scope would always be empty
+    let initial = if selected.is_empty() {
+        Scope::View
+    } else {
+        prefs.default_scope
+    };
+
+    let mut win = Window::default().with_size(500, 400).with_label(action);
+    win.set_border(true);
+    let mut flex = Flex::new(10, 10, 480, 380, "").column();
+    flex.set_spacing(5);
+
+    let mut summary = Frame::default();
+    summary.set_align(fltk::enums::Align::Left | fltk::enums::Align::Inside);
+    flex.fixed(&summary, 20);
+    let mut radio_sel = fltk::button::RadioRoundButton::default()
+        .with_label(&format!("Selected repositories ({})", selected.len()));
+    flex.fixed(&radio_sel, 25);
+    let mut radio_view = fltk::button::RadioRoundButton::default()
+        .with_label(&format!("All repositories in view ({})", in_view.len()));
+    flex.fixed(&radio_view, 25);
+    let mut list = fltk::browser::Browser::default();
+
+    let btn_row = Flex::default().row();
+    let mut btn_cancel = Button::default().with_label("Cancel");
+    let mut btn_ok = Button::default().with_label("Run");
+    btn_row.end();
+    flex.fixed(&btn_row, 30);
+
+    flex.end();
+    win.end();
+    win.make_modal(true);
+
+    fn fill(
+        list: &mut fltk::browser::Browser,
+        summary: &mut Frame,
+        ok: &mut Button,
+        action: &str,
+        targets: &[Repository],
+    ) {
+        list.clear();
+        for r in targets {
+            list.add(&r.path.display().to_string());
+        }
+        summary.set_label(&format!("{} will run on {} repositories:", action, targets.len()));
+        if targets.is_empty() {
+            ok.deactivate();
+        } else {
+            ok.activate();
+        }
+    }
+
+    let chosen = Rc::new(Cell::new(initial));
+    match initial {
+        Scope::Selected => radio_sel.set_value(true),
+        Scope::View => radio_view.set_value(true),
+    }
+    let initial_targets = match initial {
+        Scope::Selected => &selected,
+        Scope::View => &in_view,
+    };
+    fill(&mut list, &mut summary, &mut btn_ok, action, initial_targets);
+
+    for (radio, scope, targets) in [
+        (&mut radio_sel, Scope::Selected, selected.clone()),
+        (&mut radio_view, Scope::View, in_view.clone()),
+    ] {
+        let (mut list, mut summary, mut ok) = (list.clone(), summary.clone(), btn_ok.clone());
+        let action = action.to_string();
+        let chosen = chosen.clone();
+        radio.set_callback(move |_| {
+            chosen.set(scope);
+            fill(&mut list, &mut summary, &mut ok, &action, &targets);
+        });
+    }
+
+    let confirmed = Rc::new(Cell::new(false));
+    let mut win_c = win.clone();
+    btn_cancel.set_callback(move |_| win_c.hide());
+    let mut win_c = win.clone();
+    let confirmed_c = confirmed.clone();
+    btn_ok.set_callback(move |_| {
+        confirmed_c.set(true);
+        win_c.hide();
+    });
+
+    win.show();
+    while win.shown() {
+        app::wait();
+    }
+
+    if !confirmed.get() {
+        return None;
+    }
+    match chosen.get() {
+        Scope::Selected => Some(selected),
+        Scope::View => Some(in_view),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rev_number_reads_local_revisions() {
+        assert_eq!(rev_number("123"), Some(123));
+        assert_eq!(rev_number("123+"), Some(123));
+        assert_eq!(rev_number("?"), None);
+        assert_eq!(rev_number(""), None);
+    }
+
+    /// Repositories that tie on some columns and differ on others, unknown
+    /// revisions and missing values included.
+    fn sample_repos() -> Vec<Repository> {
+        let mut repos = Vec::new();
+        for (i, name) in ["delta", "alpha", "charlie", "bravo", "echo"].iter().enumerate() {
+            let mut repo = Repository::new(PathBuf::from(format!("/work/{}", name)));
+            repo.current_branch = (i % 2 == 0).then(|| "default".to_string());
+            repo.revision = match i {
+                0 => "12+".to_string(),
+                1 => "?".to_string(),
+                _ => (i * 3).to_string(),
+            };
+            repo.node_short = format!("{:x}", 0xabc0 + (i * 7) % 5);
+            repo.modified = i % 3 == 0;
+            repo.needs_merge = i == 4;
+            repo.phase = [None, Some(Phase::Draft), Some(Phase::Public)][i % 3];
+            repo.last_status = ["Success", "2 incoming", "Error: abort", "Success", "10 incoming"][i].to_string();
+            repo.latest_tag = if i < 2 { String::new() } else { "v1.0".to_string() };
+            repo.last_author = ["ann", "bob", "ann", "", "bob"][i].to_string();
+            repo.last_commit_time = (i != 2).then_some(1_700_000_000 - (i as i64 % 2) * 3600);
+            repo.last_op_duration = (i % 2 == 1).then(|| Duration::from_millis(100 * i as u64));
+            repos.push(repo);
+        }
+        repos
+    }
+
+    #[test]
+    fn sort_repos_is_idempotent_for_every_column() {
+        let prefs = Preferences::default();
+        for (column, spec) in COLUMNS.iter().enumerate() {
+            for order in [SortOrder::Ascending, SortOrder::Descending] {
+                let state = SortState { keys: vec![(column, order)] };
+                let mut repos = sample_repos();
+                sort_repos(&mut repos, &state, &prefs);
+                let once: Vec<PathBuf> = repos.iter().map(|r| r.path.clone()).collect();
+                sort_repos(&mut repos, &state, &prefs);
+                let twice: Vec<PathBuf> = repos.iter().map(|r| r.path.clone()).collect();
+                assert_eq!(once, twice, "column {} {:?}", spec.name, order);
+            }
+        }
+    }
+
+    fn target_names(targets: &[Repository]) -> Vec<String> {
+        targets.iter().map(|r| r.path.file_name().unwrap().to_string_lossy().to_string()).collect()
+    }
+
+    #[test]
+    fn targets_follow_the_selection() {
+        let prefs = Preferences::default();
+        let repos = sample_repos();
+        let targets = targets_for_lines(Scope::Selected, &[2, 4], 5, &repos, &prefs);
+        assert_eq!(target_names(&targets), ["alpha", "bravo"]);
+    }
+
+    #[test]
+    fn empty_selection_has_no_targets() {
+        let prefs = Preferences::default();
+        let repos = sample_repos();
+        assert!(targets_for_lines(Scope::Selected, &[], 5, &repos, &prefs).is_empty());
+    }
+
+    #[test]
+    fn view_scope_takes_every_line() {
+        let prefs = Preferences::default();
+        let repos = sample_repos();
+        let targets = targets_for_lines(Scope::View, &[1], 5, &repos, &prefs);
+        assert_eq!(target_names(&targets), ["delta", "alpha", "charlie", "bravo", "echo"]);
+    }
+
+    #[test]
+    fn view_scope_respects_the_filter_and_skips_group_headers() {
+        let prefs = Preferences { modified_only: true, group_by_parent: true, ..Preferences::default() };
+        let repos = sample_repos();
+        // Modified: delta, bravo; failed: charlie. One group header comes first.
+        let line_count = layout_rows(&repos, &prefs).len() as i32;
+        assert_eq!(line_count, 4);
+        let targets = targets_for_lines(Scope::View, &[], line_count, &repos, &prefs);
+        assert_eq!(target_names(&targets), ["delta", "charlie", "bravo"]);
+        // Selecting the header alone targets nothing
+        assert!(targets_for_lines(Scope::Selected, &[1], line_count, &repos, &prefs).is_empty());
+    }
+
+    #[test]
+    fn missing_repos_are_never_targets() {
+        let prefs = Preferences { modified_only: true, ..Preferences::default() };
+        let mut repos = sample_repos();
+        repos[3].missing = true;
+        // Lines: delta, charlie, bravo (missing)
+        let line_count = layout_rows(&repos, &prefs).len() as i32;
+        assert_eq!(line_count, 3);
+        let targets = targets_for_lines(Scope::Selected, &[1, 3], line_count, &repos, &prefs);
+        assert_eq!(target_names(&targets), ["delta"]);
+        let targets = targets_for_lines(Scope::View, &[], line_count, &repos, &prefs);
+        assert_eq!(target_names(&targets), ["delta", "charlie"]);
+        assert!(targets_for_lines(Scope::Selected, &[3], line_count, &repos, &prefs).is_empty());
+    }
+
+    fn paths(names: &[&str]) -> HashSet<PathBuf> {
+        names.iter().map(|name| PathBuf::from(format!("/work/{}", name))).collect()
+    }
+
+    #[test]
+    fn lines_for_paths_follow_the_sort() {
+        let prefs = Preferences::default();
+        let mut repos = sample_repos();
+        let wanted = paths(&["bravo", "echo"]);
+        assert_eq!(lines_for_paths(&repos, &prefs, &wanted), [4, 5]);
+
+        let path_column = COLUMNS.iter().position(|c| c.id == ColumnId::Path).unwrap();
+        let state = SortState { keys: vec![(path_column, SortOrder::Ascending)] };
+        sort_repos(&mut repos, &state, &prefs);
+        assert_eq!(lines_for_paths(&repos, &prefs, &wanted), [2, 5]);
+    }
+
+    #[test]
+    fn lines_for_paths_skip_group_headers_and_filtered_repos() {
+        let prefs = Preferences { modified_only: true, group_by_parent: true, ..Preferences::default() };
+        let mut repos = sample_repos();
+        repos[0].path = PathBuf::from("/archive/delta");
+        // Lines: /archive, delta, /work, charlie, bravo. Alpha and echo are filtered out.
+        let mut wanted = paths(&["alpha", "bravo", "echo"]);
+        wanted.insert(PathBuf::from("/archive/delta"));
+        assert_eq!(lines_for_paths(&repos, &prefs, &wanted), [2, 5]);
+        assert!(lines_for_paths(&repos, &prefs, &paths(&["alpha", "echo"])).is_empty());
+    }
+
+    #[test]
+    fn markdown_table_keeps_each_repository_on_one_row() {
+        let prefs = Preferences::default();
+        let mut repo = Repository::new(PathBuf::from("/work/a|b"));
+        repo.last_status = "Error: abort: C:\\repo\r\nnot found\nat all".to_string();
+        let table = markdown_table(std::slice::from_ref(&repo), &prefs);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].contains("a\\|b"));
+        assert!(lines[2].contains("Error: abort: C:\\\\repo<br>not found<br>at all"));
+    }
+
+    #[test]
+    fn patterns_match_the_whole_text() {
+        assert!(matches_pattern("lib", "/work/libfoo", true));
+        assert!(matches_pattern("*/lib?oo", "/work/libfoo", true));
+        assert!(matches_pattern("*LIB*", "/work/libfoo", false));
+        assert!(!matches_pattern("*/lib", "/work/libfoo", true));
+        assert!(matches_pattern("a*b*c", "aXbYbZc", true));
+        assert!(!matches_pattern("a*b?c", "abc", true));
+        // Would take exponential time with naive backtracking
+        let text = "a".repeat(60);
+        assert!(!matches_pattern(&format!("{}b", "*a".repeat(20)), &text, true));
+    }
+
+    #[test]
+    fn unknown_branch_never_shows_as_error() {
+        let prefs = Preferences::default();
+        let mut repo = Repository::new(PathBuf::from("/work/broken"));
+        repo.branch_error = Some("abort: repository is locked".to_string());
+
+        assert_eq!(cell_text(ColumnId::Branch, &repo, &prefs), "—");
+        for column in COLUMNS {
+            assert!(!cell_text(column.id, &repo, &prefs).contains("ERROR"), "column {}", column.name);
+        }
+        assert!(!markdown_table(std::slice::from_ref(&repo), &prefs).contains("ERROR"));
+        assert_eq!(repo_tooltip(&repo), "Branch unknown: abort: repository is locked");
+
+        let error = expand_commit_template("Merge {branch}", &repo).unwrap_err();
+        assert_eq!(error.to_string(), "current branch unknown");
+    }
+}
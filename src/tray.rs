@@ -0,0 +1,373 @@
+//! Notification area icon on Windows, to keep ManaHg running without a taskbar
+//! button. Clicking the icon shows or hides the main window; its menu has
+//! Show/Hide, Refresh All and Quit. The icon runs on a thread of its own, which
+//! only sends `Message`s to the UI thread.
+
+#[cfg(not(target_os = "windows"))]
+use fltk::{app, window::Window};
+
+#[cfg(not(target_os = "windows"))]
+use crate::Message;
+
+#[cfg(target_os = "windows")]
+pub use win32::{hide_window, install, remove, toggle_window};
+
+/// No tray elsewhere, so closing the window always quits.
+#[cfg(not(target_os = "windows"))]
+pub fn install(_win: &Window, _sender: app::Sender<Message>) -> bool {
+    false
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn toggle_window(_win: &Window) {}
+
+#[cfg(not(target_os = "windows"))]
+pub fn hide_window(_win: &Window) {}
+
+#[cfg(not(target_os = "windows"))]
+pub fn remove() {}
+
+#[cfg(target_os = "windows")]
+mod win32 {
+    use std::{
+        ffi::c_void,
+        ptr::{null, null_mut},
+        sync::{
+            atomic::{AtomicPtr, AtomicU32, Ordering},
+            OnceLock,
+        },
+    };
+
+    use fltk::{app, prelude::*, window::Window};
+
+    use crate::Message;
+
+    type Hwnd = *mut c_void;
+
+    const WM_NULL: u32 = 0x0000;
+    const WM_LBUTTONUP: u32 = 0x0202;
+    const WM_RBUTTONUP: u32 = 0x0205;
+    /// Sent by the icon to the tray window, with the mouse message in `lparam`
+    const WM_TRAY: u32 = 0x8000 + 1;
+    const NIM_ADD: u32 = 0;
+    const NIM_DELETE: u32 = 2;
+    const NIF_MESSAGE: u32 = 0x1;
+    const NIF_ICON: u32 = 0x2;
+    const NIF_TIP: u32 = 0x4;
+    const IDI_APPLICATION: usize = 32512;
+    const MF_STRING: u32 = 0x0;
+    const MF_SEPARATOR: u32 = 0x800;
+    const TPM_RIGHTBUTTON: u32 = 0x2;
+    const TPM_NONOTIFY: u32 = 0x80;
+    const TPM_RETURNCMD: u32 = 0x100;
+    const SW_HIDE: i32 = 0;
+    const SW_SHOW: i32 = 5;
+    const SW_RESTORE: i32 = 9;
+    const ICON_ID: u32 = 1;
+    const MENU_TOGGLE: usize = 1;
+    const MENU_REFRESH: usize = 2;
+    const MENU_QUIT: usize = 3;
+
+    /// Hidden window receiving the icon's messages, null until the icon is added
+    static TRAY_WINDOW: AtomicPtr<c_void> = AtomicPtr::new(null_mut());
+    /// The main window, for the Show/Hide label of the menu
+    static MAIN_WINDOW: AtomicPtr<c_void> = AtomicPtr::new(null_mut());
+    /// Broadcast when Explorer restarts, after which the icon must be added again
+    static TASKBAR_CREATED: AtomicU32 = AtomicU32::new(0);
+    static SENDER: OnceLock<app::Sender<Message>> = OnceLock::new();
+
+    #[repr(C)]
+    struct WndClassW {
+        style: u32,
+        wnd_proc: unsafe extern "system" fn(Hwnd, u32, usize, isize) -> isize,
+        cls_extra: i32,
+        wnd_extra: i32,
+        instance: *mut c_void,
+        icon: *mut c_void,
+        cursor: *mut c_void,
+        background: *mut c_void,
+        menu_name: *const u16,
+        class_name: *const u16,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[repr(C)]
+    struct Msg {
+        hwnd: Hwnd,
+        message: u32,
+        wparam: usize,
+        lparam: isize,
+        time: u32,
+        pt: Point,
+    }
+
+    #[repr(C)]
+    struct NotifyIconDataW {
+        size: u32,
+        hwnd: Hwnd,
+        id: u32,
+        flags: u32,
+        callback_message: u32,
+        icon: *mut c_void,
+        tip: [u16; 128],
+        state: u32,
+        state_mask: u32,
+        info: [u16; 256],
+        version: u32,
+        info_title: [u16; 64],
+        info_flags: u32,
+        guid: [u8; 16],
+        balloon_icon: *mut c_void,
+    }
+
+    #[link(name = "user32")]
+    unsafe extern "system" {
+        fn RegisterClassW(class: *const WndClassW) -> u16;
+        fn CreateWindowExW(
+            ex_style: u32,
+            class_name: *const u16,
+            window_name: *const u16,
+            style: u32,
+            x: i32,
+            y: i32,
+            width: i32,
+            height: i32,
+            parent: Hwnd,
+            menu: *mut c_void,
+            instance: *mut c_void,
+            param: *mut c_void,
+        ) -> Hwnd;
+        fn DefWindowProcW(hwnd: Hwnd, msg: u32, wparam: usize, lparam: isize) -> isize;
+        fn GetMessageW(msg: *mut Msg, hwnd: Hwnd, filter_min: u32, filter_max: u32) -> i32;
+        fn TranslateMessage(msg: *const Msg) -> i32;
+        fn DispatchMessageW(msg: *const Msg) -> isize;
+        fn RegisterWindowMessageW(name: *const u16) -> u32;
+        fn LoadIconW(instance: *mut c_void, name: *const u16) -> *mut c_void;
+        fn CreatePopupMenu() -> *mut c_void;
+        fn AppendMenuW(menu: *mut c_void, flags: u32, id: usize, text: *const u16) -> i32;
+        fn TrackPopupMenu(
+            menu: *mut c_void,
+            flags: u32,
+            x: i32,
+            y: i32,
+            reserved: i32,
+            hwnd: Hwnd,
+            rect: *const c_void,
+        ) -> i32;
+        fn DestroyMenu(menu: *mut c_void) -> i32;
+        fn GetCursorPos(point: *mut Point) -> i32;
+        fn SetForegroundWindow(hwnd: Hwnd) -> i32;
+        fn PostMessageW(hwnd: Hwnd, msg: u32, wparam: usize, lparam: isize) -> i32;
+        fn ShowWindow(hwnd: Hwnd, cmd: i32) -> i32;
+        fn IsWindowVisible(hwnd: Hwnd) -> i32;
+        fn IsIconic(hwnd: Hwnd) -> i32;
+    }
+
+    #[link(name = "shell32")]
+    unsafe extern "system" {
+        fn Shell_NotifyIconW(message: u32, data: *mut NotifyIconDataW) -> i32;
+    }
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetModuleHandleW(name: *const u16) -> *mut c_void;
+    }
+
+    /// Adds the icon for `win`, which must be shown, and starts its thread.
+    /// False when the icon could not be added, e.g. without a taskbar.
+    pub fn install(win: &Window, sender: app::Sender<Message>) -> bool {
+        MAIN_WINDOW.store(win.raw_handle(), Ordering::Relaxed);
+        let _ = SENDER.set(sender);
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let hwnd = create_tray_window();
+            let added = !hwnd.is_null() && add_icon(hwnd);
+            if added {
+                TRAY_WINDOW.store(hwnd, Ordering::Relaxed);
+            }
+            let _ = ready_tx.send(added);
+            if !added {
+                return;
+            }
+            // The window's messages are only delivered to the thread that created it
+            let mut msg = Msg {
+                hwnd: null_mut(),
+                message: 0,
+                wparam: 0,
+                lparam: 0,
+                time: 0,
+                pt: Point::default(),
+            };
+            while unsafe { GetMessageW(&mut msg, null_mut(), 0, 0) } > 0 {
+                unsafe {
+                    TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+            }
+        });
+        ready_rx.recv().unwrap_or(false)
+    }
+
+    /// Removes the icon; Windows would otherwise leave it until hovered.
+    pub fn remove() {
+        let hwnd = TRAY_WINDOW.swap(null_mut(), Ordering::Relaxed);
+        if !hwnd.is_null() {
+            let mut data = icon_data(hwnd);
+            unsafe { Shell_NotifyIconW(NIM_DELETE, &mut data) };
+        }
+    }
+
+    /// Hides `win` if it is on screen, otherwise shows it in front, restored
+    /// if it was minimized.
+    pub fn toggle_window(win: &Window) {
+        let hwnd = win.raw_handle();
+        if window_on_screen(hwnd) {
+            unsafe { ShowWindow(hwnd, SW_HIDE) };
+        } else {
+            unsafe {
+                ShowWindow(hwnd, if IsIconic(hwnd) != 0 { SW_RESTORE } else { SW_SHOW });
+                SetForegroundWindow(hwnd);
+            }
+        }
+    }
+
+    /// Hides `win` along with its taskbar button. This goes around FLTK, which
+    /// would destroy the window and leave the event loop with nothing shown.
+    pub fn hide_window(win: &Window) {
+        unsafe { ShowWindow(win.raw_handle(), SW_HIDE) };
+    }
+
+    fn window_on_screen(hwnd: Hwnd) -> bool {
+        !hwnd.is_null() && unsafe { IsWindowVisible(hwnd) != 0 && IsIconic(hwnd) == 0 }
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(Some(0)).collect()
+    }
+
+    fn send(msg: Message) {
+        if let Some(sender) = SENDER.get() {
+            sender.send(msg);
+        }
+    }
+
+    fn create_tray_window() -> Hwnd {
+        let class_name = wide("ManaHgTray");
+        unsafe {
+            let instance = GetModuleHandleW(null());
+            let class = WndClassW {
+                style: 0,
+                wnd_proc: tray_proc,
+                cls_extra: 0,
+                wnd_extra: 0,
+                instance,
+                icon: null_mut(),
+                cursor: null_mut(),
+                background: null_mut(),
+                menu_name: null(),
+                class_name: class_name.as_ptr(),
+            };
+            RegisterClassW(&class);
+            TASKBAR_CREATED.store(RegisterWindowMessageW(wide("TaskbarCreated").as_ptr()), Ordering::Relaxed);
+            // Never shown; a message-only window cannot own the popup menu
+            CreateWindowExW(
+                0,
+                class_name.as_ptr(),
+                class_name.as_ptr(),
+                0,
+                0,
+                0,
+                0,
+                0,
+                null_mut(),
+                null_mut(),
+                instance,
+                null_mut(),
+            )
+        }
+    }
+
+    fn icon_data(hwnd: Hwnd) -> NotifyIconDataW {
+        NotifyIconDataW {
+            size: size_of::<NotifyIconDataW>() as u32,
+            hwnd,
+            id: ICON_ID,
+            flags: 0,
+            callback_message: 0,
+            icon: null_mut(),
+            tip: [0; 128],
+            state: 0,
+            state_mask: 0,
+            info: [0; 256],
+            version: 0,
+            info_title: [0; 64],
+            info_flags: 0,
+            guid: [0; 16],
+            balloon_icon: null_mut(),
+        }
+    }
+
+    fn add_icon(hwnd: Hwnd) -> bool {
+        let mut data = icon_data(hwnd);
+        data.flags = NIF_MESSAGE | NIF_ICON | NIF_TIP;
+        data.callback_message = WM_TRAY;
+        data.icon = unsafe { LoadIconW(null_mut(), IDI_APPLICATION as *const u16) };
+        for (dst, src) in data.tip.iter_mut().zip("ManaHg".encode_utf16()) {
+            *dst = src;
+        }
+        unsafe { Shell_NotifyIconW(NIM_ADD, &mut data) != 0 }
+    }
+
+    unsafe extern "system" fn tray_proc(hwnd: Hwnd, msg: u32, wparam: usize, lparam: isize) -> isize {
+        if msg == WM_TRAY {
+            match lparam as u32 {
+                WM_LBUTTONUP => send(Message::ToggleWindow),
+                WM_RBUTTONUP => show_menu(hwnd),
+                _ => {}
+            }
+            return 0;
+        }
+        if msg != 0 && msg == TASKBAR_CREATED.load(Ordering::Relaxed) {
+            add_icon(hwnd);
+            return 0;
+        }
+        unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) }
+    }
+
+    fn show_menu(hwnd: Hwnd) {
+        let toggle = if window_on_screen(MAIN_WINDOW.load(Ordering::Relaxed)) {
+            "Hide ManaHg"
+        } else {
+            "Show ManaHg"
+        };
+        let items = [(MENU_TOGGLE, toggle), (MENU_REFRESH, "Refresh All"), (0, ""), (MENU_QUIT, "Quit")];
+        let chosen = unsafe {
+            let menu = CreatePopupMenu();
+            for (id, label) in items {
+                let flags = if id == 0 { MF_SEPARATOR } else { MF_STRING };
+                AppendMenuW(menu, flags, id, wide(label).as_ptr());
+            }
+            let mut pos = Point::default();
+            GetCursorPos(&mut pos);
+            // Otherwise the menu stays open when clicking elsewhere
+            SetForegroundWindow(hwnd);
+            let flags = TPM_RIGHTBUTTON | TPM_NONOTIFY | TPM_RETURNCMD;
+            let chosen = TrackPopupMenu(menu, flags, pos.x, pos.y, 0, hwnd, null());
+            PostMessageW(hwnd, WM_NULL, 0, 0);
+            DestroyMenu(menu);
+            chosen as usize
+        };
+        match chosen {
+            MENU_TOGGLE => send(Message::ToggleWindow),
+            MENU_REFRESH => send(Message::RefreshAll),
+            MENU_QUIT => send(Message::Quit),
+            _ => {}
+        }
+    }
+}